@@ -22,4 +22,12 @@ impl Address {
 
         handle.write_all(&buf).unwrap();
     }
+
+    pub fn read_record(&self, mut file: &File) -> std::io::Result<Vec<u8>> {
+        let mut buf = vec![0u8; self.length as usize];
+        file.seek(SeekFrom::Start(self.offset))?;
+        file.read_exact(&mut buf)?;
+
+        Ok(buf)
+    }
 }
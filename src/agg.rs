@@ -0,0 +1,306 @@
+use crate::decimal;
+use crate::expression::Expression;
+use crate::filter::Filter;
+use crate::range::Range;
+use crate::toc::{Toc, TypedToc};
+use crate::unsafe_float::UnsafeFloat;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use std::error::Error;
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::Write;
+
+use std::ops::Bound::{Excluded, Unbounded};
+
+/// Order statistics of a weighted, sorted list of keys
+#[derive(Debug, PartialEq)]
+pub struct Stats<R> {
+    pub count: u64,
+    pub min: R,
+    pub max: R,
+    pub median: R,
+    pub p95: R,
+    pub p99: R,
+}
+
+impl<R: Copy> Stats<R> {
+    /// Compute the statistics from (key, occurrences) pairs, sorted by key
+    pub fn from(counts: &[(R, u64)]) -> Option<Self> {
+        let count = counts.iter().map(|(_, c)| c).sum();
+        if count == 0 {
+            return None;
+        }
+
+        Some(Stats {
+            count,
+            min: counts.first()?.0,
+            max: counts.last()?.0,
+            median: quantile(counts, count, 0.5),
+            p95: quantile(counts, count, 0.95),
+            p99: quantile(counts, count, 0.99),
+        })
+    }
+}
+
+/// Nearest-rank quantile: the smallest key with at least q * count values at or below it
+fn quantile<R: Copy>(counts: &[(R, u64)], count: u64, q: f64) -> R {
+    let rank = ((q * count as f64).ceil() as u64).max(1);
+
+    let mut seen = 0;
+    for (key, c) in counts {
+        seen += c;
+        if seen >= rank {
+            return *key;
+        }
+    }
+
+    counts[counts.len() - 1].0
+}
+
+const HEADER: [&str; 8] = ["count", "sum", "min", "max", "mean", "median", "p95", "p99"];
+
+pub struct Agg<'a> {
+    column: &'a Expression,
+    range: Option<Filter<'a>>,
+}
+
+impl<'a> Agg<'a> {
+    pub fn from(column: &'a Expression, range: Option<Filter<'a>>) -> Self {
+        Agg { column, range }
+    }
+
+    pub fn execute<W: Write>(&self, filename: &str, writer: W) -> Result<(), Box<dyn Error>> {
+        let mut fh = File::open(self.column.index_path(filename))?;
+        let typed_toc = TypedToc::open(&mut fh)?;
+        if typed_toc.is_sparse() {
+            Err("A sparse index does not hold all values of a column")?
+        }
+
+        let mut wtr = csv::Writer::from_writer(writer);
+
+        match typed_toc {
+            TypedToc::STR(..)
+            | TypedToc::TIME(..)
+            | TypedToc::I128(_)
+            | TypedToc::U128(_)
+            | TypedToc::HEX(_)
+            | TypedToc::IP(_)
+            | TypedToc::UUID(_)
+            | TypedToc::TRIGRAM(..)
+            | TypedToc::TEXT(..)
+            | TypedToc::HASH(..) => Err("Aggregations need an int, u64, float or decimal index")?,
+            TypedToc::I64(toc, number_format) => {
+                let bounds = match &self.range {
+                    Some(filter) => filter.int_bounds(&number_format)?,
+                    None => (Unbounded, Unbounded),
+                };
+                let counts = Self::counts(toc, &mut fh, bounds)?;
+
+                wtr.write_record(HEADER)?;
+                write_int_stats(&mut wtr, &counts)?;
+            }
+            TypedToc::U64(toc) => {
+                let bounds = match &self.range {
+                    Some(filter) => filter.u64_bounds()?,
+                    None => (Unbounded, Unbounded),
+                };
+                let counts = Self::counts(toc, &mut fh, bounds)?;
+
+                wtr.write_record(HEADER)?;
+                write_int_stats(&mut wtr, &counts)?;
+            }
+            TypedToc::F64(toc, number_format) => {
+                let bounds = match &self.range {
+                    Some(filter) => filter.float_bounds(&number_format)?,
+                    None => (Unbounded, Excluded(UnsafeFloat::NAN)),
+                };
+                let counts = Self::counts(toc, &mut fh, bounds)?;
+
+                wtr.write_record(HEADER)?;
+
+                if let Some(stats) = Stats::from(&counts) {
+                    let sum: f64 = counts.iter().map(|(key, c)| key.0 * *c as f64).sum();
+                    let mean = sum / stats.count as f64;
+                    wtr.write_record(&[
+                        stats.count.to_string(),
+                        sum.to_string(),
+                        stats.min.0.to_string(),
+                        stats.max.0.to_string(),
+                        mean.to_string(),
+                        stats.median.0.to_string(),
+                        stats.p95.0.to_string(),
+                        stats.p99.0.to_string(),
+                    ])?;
+                } else {
+                    wtr.write_record(["0", "0", "", "", "", "", "", ""])?;
+                }
+            }
+            TypedToc::DECIMAL(toc, decimal_type) => {
+                let bounds = match &self.range {
+                    Some(filter) => filter.decimal_bounds(&decimal_type)?,
+                    None => (Unbounded, Unbounded),
+                };
+                let counts = Self::counts(toc, &mut fh, bounds)?;
+
+                wtr.write_record(HEADER)?;
+
+                if let Some(stats) = Stats::from(&counts) {
+                    let scale = decimal_type.scale();
+                    let format = |key: i64| decimal::format(i128::from(key), scale);
+
+                    let sum: i128 = counts
+                        .iter()
+                        .map(|(key, c)| i128::from(*key) * i128::from(*c))
+                        .sum();
+                    // two more digits than the values, rounded half away from zero
+                    let count = i128::from(stats.count);
+                    let mean = (sum * 200 + sum.signum() * count) / (2 * count);
+                    wtr.write_record(&[
+                        stats.count.to_string(),
+                        decimal::format(sum, scale),
+                        format(stats.min),
+                        format(stats.max),
+                        decimal::format(mean, scale + 2),
+                        format(stats.median),
+                        format(stats.p95),
+                        format(stats.p99),
+                    ])?;
+                } else {
+                    wtr.write_record(["0", "0", "", "", "", "", "", ""])?;
+                }
+            }
+        };
+
+        wtr.flush()?;
+        Ok(())
+    }
+
+    /// Collect the (key, occurrences) pairs within bounds, sorted by key
+    fn counts<R>(
+        toc: Toc<R>,
+        fh: &mut File,
+        bounds: Range<R>,
+    ) -> Result<Vec<(R, u64)>, Box<dyn Error>>
+    where
+        R: Ord + Serialize + DeserializeOwned + Clone + Debug,
+    {
+        let indexes = toc.get_index(fh, &bounds)?;
+        let counts = indexes
+            .iter()
+            .flat_map(|index| {
+                let b_clone = (bounds.0.clone(), bounds.1.clone());
+                index.value_counts(b_clone)
+            })
+            .map(|(key, count)| (key.clone(), count as u64))
+            .collect();
+
+        Ok(counts)
+    }
+}
+
+/// Statistics of 64 bit integer keys, summed exactly in 128 bits
+fn write_int_stats<R, W>(
+    wtr: &mut csv::Writer<W>,
+    counts: &[(R, u64)],
+) -> Result<(), Box<dyn Error>>
+where
+    R: Copy + Into<i128> + ToString,
+    W: Write,
+{
+    if let Some(stats) = Stats::from(counts) {
+        let sum: i128 = counts
+            .iter()
+            .map(|(key, c)| (*key).into() * i128::from(*c))
+            .sum();
+        let mean = sum as f64 / stats.count as f64;
+        wtr.write_record(&[
+            stats.count.to_string(),
+            sum.to_string(),
+            stats.min.to_string(),
+            stats.max.to_string(),
+            mean.to_string(),
+            stats.median.to_string(),
+            stats.p95.to_string(),
+            stats.p99.to_string(),
+        ])?;
+    } else {
+        wtr.write_record(["0", "0", "", "", "", "", "", ""])?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::Operator;
+    use crate::index::IndexOptions;
+    use crate::test_files::CsvFile;
+
+    #[test]
+    fn test_empty() {
+        let counts: Vec<(i64, u64)> = vec![];
+        assert_eq!(Stats::from(&counts), None);
+    }
+
+    #[test]
+    fn test_single() {
+        let counts = vec![(7, 3)];
+        let stats = Stats::from(&counts).unwrap();
+        assert_eq!(stats.count, 3);
+        assert_eq!(stats.min, 7);
+        assert_eq!(stats.max, 7);
+        assert_eq!(stats.median, 7);
+        assert_eq!(stats.p99, 7);
+    }
+
+    #[test]
+    fn test_weighted_quantiles() {
+        // 1..=100, with 100 occurring 5 times
+        let mut counts: Vec<(i64, u64)> = (1..100).map(|i| (i, 1)).collect();
+        counts.push((100, 5));
+
+        let stats = Stats::from(&counts).unwrap();
+        assert_eq!(stats.count, 104);
+        assert_eq!(stats.min, 1);
+        assert_eq!(stats.max, 100);
+        assert_eq!(stats.median, 52);
+        assert_eq!(stats.p95, 99);
+        assert_eq!(stats.p99, 100);
+    }
+
+    #[test]
+    fn test_execute() {
+        let input = b"id,price\n1,1.5\n2,nan\n3,\n4,2.5\n5,4\n";
+        let mut csv = CsvFile::new("agg", input);
+        let price = Expression::Column(1);
+        csv.index(&price, "float", &IndexOptions::default())
+            .unwrap();
+
+        let agg = |range: Option<Filter>| {
+            let mut output = vec![];
+            Agg::from(&price, range)
+                .execute(csv.path(), &mut output)
+                .unwrap();
+            String::from_utf8(output).unwrap()
+        };
+        let header = "count,sum,min,max,mean,median,p95,p99\n";
+
+        // nan and empty values are left out
+        assert_eq!(
+            agg(None),
+            format!("{}3,8,1.5,4,2.6666666666666665,2.5,4,4\n", header)
+        );
+        let not_null = Filter::from(Operator::NOTNULL, "", "", &price);
+        assert_eq!(agg(Some(not_null)), agg(None));
+
+        let range = Filter::from(Operator::LE, "3", "", &price);
+        assert_eq!(
+            agg(Some(range)),
+            format!("{}2,4,1.5,2.5,2,1.5,2.5,2.5\n", header)
+        );
+    }
+}
@@ -8,6 +8,11 @@ pub fn chunk_map<K: Debug + Ord + Clone, V>(
 ) -> Vec<(K, BTreeMap<K, V>)> {
     let mut chunked_map: Vec<(K, BTreeMap<K, V>)> = Vec::with_capacity(pieces);
 
+    // e.g. a column holding only nulls
+    if map.is_empty() {
+        return chunked_map;
+    }
+
     let count = map.len();
     let chunk_size = count / pieces;
 
@@ -30,6 +35,24 @@ pub fn chunk_map<K: Debug + Ord + Clone, V>(
     chunked_map
 }
 
+/// Split a map keyed by hashes into pieces covering equal parts of the hash space
+///
+/// Unlike `chunk_map`, the piece of a key follows from its hash alone, and uniform hashes give
+/// pieces of about the same size.
+pub fn chunk_hashes<V>(map: &mut BTreeMap<u64, V>, pieces: usize) -> Vec<(u64, BTreeMap<u64, V>)> {
+    let mut chunked_map = Vec::with_capacity(pieces);
+
+    for piece in (1..pieces).rev() {
+        let start = ((piece as u128) << 64) / pieces as u128;
+        let split = map.split_off(&(start as u64));
+        chunked_map.push((start as u64, split));
+    }
+    chunked_map.push((0, std::mem::take(map)));
+    chunked_map.reverse();
+
+    chunked_map
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -52,6 +75,14 @@ mod tests {
         assert_eq!(chunk.get(&3), Some(&'c'));
     }
 
+    #[test]
+    fn test_split_empty() {
+        let mut map: BTreeMap<u8, char> = BTreeMap::new();
+
+        let chunked_map = chunk_map(&mut map, 2);
+        assert!(chunked_map.is_empty());
+    }
+
     #[test]
     fn test_split_two() {
         let mut map = BTreeMap::new();
@@ -73,4 +104,32 @@ mod tests {
         assert_eq!(chunk.get(&2), Some(&'b'));
         assert_eq!(chunk.get(&3), Some(&'c'));
     }
+
+    #[test]
+    fn test_chunk_hashes() {
+        let mut map = BTreeMap::new();
+        map.insert(0, 'a');
+        map.insert(u64::MAX / 4, 'b');
+        map.insert(u64::MAX / 4 + 1, 'c');
+        map.insert(u64::MAX, 'd');
+
+        let chunked_map = chunk_hashes(&mut map, 4);
+        let pieces: Vec<(u64, Vec<char>)> = chunked_map
+            .into_iter()
+            .map(|(start, chunk)| (start, chunk.values().cloned().collect()))
+            .collect();
+        assert_eq!(
+            pieces,
+            vec![
+                (0, vec!['a', 'b']),
+                (1 << 62, vec!['c']),
+                (2 << 62, vec![]),
+                (3 << 62, vec!['d']),
+            ]
+        );
+        assert!(map.is_empty());
+
+        let mut map: BTreeMap<u64, char> = BTreeMap::new();
+        assert_eq!(chunk_hashes(&mut map, 1).len(), 1);
+    }
 }
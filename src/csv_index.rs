@@ -5,41 +5,199 @@ use std::io::Seek;
 use std::io::SeekFrom;
 use std::io::Write;
 
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize, Serializer};
 use std::collections::btree_map::Entry;
 use std::collections::BTreeMap;
+use std::collections::HashMap;
 
 use crate::address::Address;
-use crate::chunked_map::chunk_map;
+use crate::chunked_map::{chunk_hashes, chunk_map};
+use crate::datetime::TimeType;
+use crate::decimal::{self, DecimalType};
+use crate::hash;
+use crate::index::IndexOptions;
+use crate::ip;
+use crate::normalize::Normalization;
+use crate::number::NumberFormat;
+use crate::printer::Printer;
 use crate::range::Range;
+use crate::sparse;
+use crate::text::Tokenizer;
 use crate::toc::{Toc, TypedToc};
+use crate::trigram;
 use crate::unsafe_float::UnsafeFloat;
+use crate::uuid;
+
+use std::fmt::Debug;
+use std::str::FromStr;
+
+use log::{debug, info, warn};
+
+/// Evaluate an expression on the index inside any variant of CsvIndexType, or the toc inside
+/// any variant of TypedToc, e.g. `each_variant!(TypedToc, self, toc => toc.is_sparse())`
+macro_rules! each_variant {
+    ($type:ident, $value:expr, $inner:ident => $body:expr) => {
+        match $value {
+            $type::STR($inner, ..) => $body,
+            $type::I64($inner, ..) => $body,
+            $type::F64($inner, ..) => $body,
+            $type::TIME($inner, ..) => $body,
+            $type::DECIMAL($inner, ..) => $body,
+            $type::U64($inner) => $body,
+            $type::I128($inner) => $body,
+            $type::U128($inner) => $body,
+            $type::HEX($inner) => $body,
+            $type::IP($inner) => $body,
+            $type::UUID($inner) => $body,
+            $type::TRIGRAM($inner, ..) => $body,
+            $type::TEXT($inner, ..) => $body,
+            $type::HASH($inner, ..) => $body,
+        }
+    };
+}
+pub(crate) use each_variant;
+
+/// Number of offending records to keep as examples of unparsable values
+const NULL_SAMPLES: usize = 5;
+
+/// Records without a usable value: empty, or not parsable as the index type
+pub struct Nulls {
+    pub addresses: Vec<Address>,
+    pub empty: u64,
+    pub invalid: u64,
+    pub samples: Vec<(Address, Vec<u8>)>,
+    max_samples: usize,
+}
 
-use std::f64;
-use std::i64;
-
-use log::{debug, info};
+impl Default for Nulls {
+    fn default() -> Self {
+        Nulls {
+            addresses: vec![],
+            empty: 0,
+            invalid: 0,
+            samples: vec![],
+            max_samples: NULL_SAMPLES,
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize)]
 pub struct CsvIndex<R: Ord> {
     map: BTreeMap<R, Vec<Address>>,
+
+    // stored in a separate chapter, see Toc::write_nulls
+    #[serde(skip)]
+    nulls: Nulls,
+
+    // records can be stored under several keys, see Toc::is_multi_valued
+    #[serde(skip)]
+    multi_valued: bool,
+
+    // values of the included columns of the records in this chapter, by offset
+    included: HashMap<u64, Vec<Vec<u8>>>,
+
+    // see Toc::included
+    #[serde(skip)]
+    included_columns: Vec<usize>,
+
+    // records per block when written as a sparse index, see Toc::is_sparse
+    #[serde(skip)]
+    block_size: Option<usize>,
 }
 
 impl<R: Ord> CsvIndex<R> {
     pub fn new() -> Self {
         CsvIndex {
             map: BTreeMap::new(),
+            nulls: Nulls::default(),
+            multi_valued: false,
+            included: HashMap::new(),
+            included_columns: vec![],
+            block_size: None,
         }
     }
 
     pub fn from(map: BTreeMap<R, Vec<Address>>) -> Self {
-        CsvIndex { map }
+        CsvIndex {
+            map,
+            nulls: Nulls::default(),
+            multi_valued: false,
+            included: HashMap::new(),
+            included_columns: vec![],
+            block_size: None,
+        }
     }
 
     pub fn into_map(self) -> BTreeMap<R, Vec<Address>> {
         self.map
     }
 
+    pub fn into_parts(self) -> (BTreeMap<R, Vec<Address>>, Nulls, HashMap<u64, Vec<Vec<u8>>>) {
+        (self.map, self.nulls, self.included)
+    }
+
+    pub fn insert_null(&mut self, raw: Vec<u8>, address: Address) {
+        if raw.is_empty() {
+            self.nulls.empty += 1;
+        } else {
+            self.nulls.invalid += 1;
+            if self.nulls.samples.len() < self.nulls.max_samples {
+                self.nulls.samples.push((address.clone(), raw));
+            }
+        }
+        self.nulls.addresses.push(address);
+    }
+
+    /// Keep every unparsable value, instead of just a few examples
+    pub fn keep_all_samples(&mut self) {
+        self.nulls.max_samples = usize::MAX;
+    }
+
+    pub fn nulls(&self) -> &Nulls {
+        &self.nulls
+    }
+
+    /// Mark that a record can be stored under several keys
+    pub fn set_multi_valued(&mut self) {
+        self.multi_valued = true;
+    }
+
+    pub fn is_multi_valued(&self) -> bool {
+        self.multi_valued
+    }
+
+    /// Store the values of these columns with every record
+    pub fn include_columns(&mut self, columns: Vec<usize>) {
+        self.included_columns = columns;
+    }
+
+    pub fn included_columns(&self) -> &[usize] {
+        &self.included_columns
+    }
+
+    pub fn include(&mut self, offset: u64, values: Vec<Vec<u8>>) {
+        self.included.insert(offset, values);
+    }
+
+    /// Only store the first key of every block of records, for a file sorted by the key
+    pub fn set_sparse(&mut self, block_size: usize) {
+        self.block_size = Some(block_size);
+    }
+
+    pub fn set_included(&mut self, included: HashMap<u64, Vec<Vec<u8>>>) {
+        self.included = included;
+    }
+
+    /// Values of the included columns of a record
+    pub fn included(&self, address: &Address) -> Option<&Vec<Vec<u8>>> {
+        self.included.get(&address.offset)
+    }
+
+    pub fn get(&self, k: &R) -> Option<&Vec<Address>> {
+        self.map.get(k)
+    }
+
     pub fn entry(&mut self, k: R) -> Entry<R, Vec<Address>> {
         self.map.entry(k)
     }
@@ -52,45 +210,159 @@ impl<R: Ord> CsvIndex<R> {
         self.map.len()
     }
 
+    pub fn range(&self, bounds: Range<R>) -> impl Iterator<Item = (&R, &Vec<Address>)> {
+        self.map.range(bounds)
+    }
+
+    pub fn value_counts(&self, bounds: Range<R>) -> impl Iterator<Item = (&R, usize)> {
+        self.map.range(bounds).map(|(key, vals)| (key, vals.len()))
+    }
+
     pub fn print_matching_records<W: Write>(
         &self,
         bounds: Range<R>,
-        file: &File,
-        mut writer: &mut W,
-    ) {
-        self.map
-            .range(bounds)
-            .flat_map(|(_key, vals)| vals.into_iter())
-            .for_each(|address| {
-                address.print_record(&mut writer, &file);
-            });
+        printer: &mut Printer<W>,
+    ) -> Result<(), Box<dyn Error>> {
+        for address in self.map.range(bounds).flat_map(|(_key, vals)| vals.iter()) {
+            printer.print(address, self.included(address))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl CsvIndex<i64> {
+    /// Multiply all keys by factor, unless that would overflow
+    pub fn rescale(&mut self, factor: i64) -> bool {
+        let fits = |key: Option<&i64>| key.map_or(true, |k| k.checked_mul(factor).is_some());
+        if !fits(self.map.keys().next()) || !fits(self.map.keys().next_back()) {
+            return false;
+        }
+
+        let map = std::mem::take(&mut self.map);
+        self.map = map.into_iter().map(|(k, v)| (k * factor, v)).collect();
+        true
+    }
+}
+
+/// Render an index key back into the bytes a user would see in the csv file
+pub trait FormatKey {
+    fn format_key(&self) -> Vec<u8>;
+}
+
+impl FormatKey for Vec<u8> {
+    fn format_key(&self) -> Vec<u8> {
+        self.clone()
+    }
+}
+
+impl FormatKey for i64 {
+    fn format_key(&self) -> Vec<u8> {
+        self.to_string().into_bytes()
+    }
+}
+
+impl FormatKey for u64 {
+    fn format_key(&self) -> Vec<u8> {
+        self.to_string().into_bytes()
+    }
+}
+
+impl FormatKey for i128 {
+    fn format_key(&self) -> Vec<u8> {
+        self.to_string().into_bytes()
+    }
+}
+
+impl FormatKey for u128 {
+    fn format_key(&self) -> Vec<u8> {
+        self.to_string().into_bytes()
+    }
+}
+
+impl FormatKey for UnsafeFloat {
+    fn format_key(&self) -> Vec<u8> {
+        self.0.to_string().into_bytes()
     }
 }
 
 pub enum CsvIndexType {
-    STR(CsvIndex<Vec<u8>>),
-    I64(CsvIndex<i64>),
-    F64(CsvIndex<UnsafeFloat>),
+    STR(CsvIndex<Vec<u8>>, Normalization),
+    I64(CsvIndex<i64>, NumberFormat),
+    F64(CsvIndex<UnsafeFloat>, NumberFormat),
+    TIME(CsvIndex<i64>, TimeType),
+    DECIMAL(CsvIndex<i64>, DecimalType),
+    U64(CsvIndex<u64>),
+    I128(CsvIndex<i128>),
+    U128(CsvIndex<u128>),
+    HEX(CsvIndex<u128>),
+    IP(CsvIndex<u128>),
+    UUID(CsvIndex<[u8; 16]>),
+    TRIGRAM(CsvIndex<Vec<u8>>, Normalization),
+    TEXT(CsvIndex<Vec<u8>>, Tokenizer),
+    HASH(CsvIndex<u64>, Normalization),
 }
 impl Serialize for CsvIndexType {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
-        match self {
-            CsvIndexType::STR(index) => index.serialize(serializer),
-            CsvIndexType::I64(index) => index.serialize(serializer),
-            CsvIndexType::F64(index) => index.serialize(serializer),
-        }
+        each_variant!(CsvIndexType, self, index => index.serialize(serializer))
     }
 }
 
 impl CsvIndexType {
-    pub fn try_new(csv_type: &str) -> Result<Self, &'static str> {
+    pub fn try_new(csv_type: &str, options: &IndexOptions) -> Result<Self, &'static str> {
+        let time_format = options.time_format.as_ref().map(|f| f.as_ref());
+        let tz = options.tz.as_ref().map(|tz| tz.as_ref());
+        if let Some(time_type) = TimeType::try_new(csv_type, time_format, tz)? {
+            return Ok(CsvIndexType::TIME(CsvIndex::<i64>::new(), time_type));
+        }
+
+        let number_format = NumberFormat::try_new(
+            options.thousands_sep.as_ref().map(|sep| sep.as_ref()),
+            options.decimal_sep.as_ref().map(|sep| sep.as_ref()),
+            options.currency,
+            options.accounting,
+        )?;
+
+        let normalization = Normalization::try_new(
+            options.fold_case,
+            options.trim,
+            options.normalize.as_ref().map(|form| form.as_ref()),
+            options.strip_accents,
+            options.reverse,
+        )?;
+
         match csv_type.to_uppercase().as_ref() {
-            "STR" => Ok(CsvIndexType::STR(CsvIndex::<Vec<u8>>::new())),
-            "INT" => Ok(CsvIndexType::I64(CsvIndex::<i64>::new())),
-            "FLOAT" => Ok(CsvIndexType::F64(CsvIndex::<UnsafeFloat>::new())),
+            "STR" => Ok(CsvIndexType::STR(CsvIndex::<Vec<u8>>::new(), normalization)),
+            "TRIGRAM" if normalization.is_reversed() => Err("A trigram index can not be reversed"),
+            "TRIGRAM" => Ok(CsvIndexType::TRIGRAM(
+                CsvIndex::<Vec<u8>>::new(),
+                normalization,
+            )),
+            "TEXT" if normalization.is_reversed() => Err("A text index can not be reversed"),
+            "TEXT" => Ok(CsvIndexType::TEXT(
+                CsvIndex::<Vec<u8>>::new(),
+                Tokenizer::new(normalization, options.stem, options.stopwords),
+            )),
+            "HASH" if normalization.is_reversed() => Err("A hash index can not be reversed"),
+            "HASH" => Ok(CsvIndexType::HASH(CsvIndex::<u64>::new(), normalization)),
+            "INT" => Ok(CsvIndexType::I64(CsvIndex::<i64>::new(), number_format)),
+            "FLOAT" => Ok(CsvIndexType::F64(
+                CsvIndex::<UnsafeFloat>::new(),
+                number_format,
+            )),
+            "DECIMAL" => Ok(CsvIndexType::DECIMAL(
+                CsvIndex::<i64>::new(),
+                DecimalType::try_new(options.scale)?,
+            )),
+            "U64" => Ok(CsvIndexType::U64(CsvIndex::<u64>::new())),
+            "I128" => Ok(CsvIndexType::I128(CsvIndex::<i128>::new())),
+            "U128" => Ok(CsvIndexType::U128(CsvIndex::<u128>::new())),
+            "HEX" => Ok(CsvIndexType::HEX(CsvIndex::<u128>::new())),
+            "IP" => Ok(CsvIndexType::IP(CsvIndex::<u128>::new())),
+            "UUID" => Ok(CsvIndexType::UUID(CsvIndex::<[u8; 16]>::new())),
             _ => Err("Unknown operator"),
         }
     }
@@ -98,143 +370,448 @@ impl CsvIndexType {
     #[inline]
     pub fn insert(&mut self, key: Vec<u8>, value: Address) {
         match self {
-            CsvIndexType::STR(index) => index.entry(key).or_insert_with(|| vec![]).push(value),
-            CsvIndexType::I64(index) => {
-                let key = std::str::from_utf8(&key)
-                    .unwrap_or("")
-                    .parse()
-                    .unwrap_or(i64::MIN);
-                index.entry(key).or_insert_with(|| vec![]).push(value)
-            }
-            CsvIndexType::F64(index) => {
-                let key = UnsafeFloat(
-                    std::str::from_utf8(&key)
-                        .unwrap_or("")
-                        .parse()
-                        .unwrap_or(f64::NEG_INFINITY),
-                );
-                index.entry(key).or_insert_with(|| vec![]).push(value)
+            CsvIndexType::STR(index, normalization) => {
+                let key = if normalization.is_identity() {
+                    key
+                } else {
+                    normalization.key(&key).into_owned()
+                };
+                index.entry(key).or_default().push(value)
+            }
+            CsvIndexType::I64(index, number_format) => match number_format.parse_int(&key) {
+                Some(k) => index.entry(k).or_default().push(value),
+                None => index.insert_null(key, value),
+            },
+            CsvIndexType::U64(index) => insert_parsed(index, key, value),
+            CsvIndexType::I128(index) => insert_parsed(index, key, value),
+            CsvIndexType::U128(index) => insert_parsed(index, key, value),
+            CsvIndexType::HEX(index) => match parse_hex(&key) {
+                Some(k) => index.entry(k).or_default().push(value),
+                None => index.insert_null(key, value),
+            },
+            CsvIndexType::IP(index) => match std::str::from_utf8(&key).ok().and_then(ip::parse) {
+                Some(k) => index.entry(k).or_default().push(value),
+                None => index.insert_null(key, value),
+            },
+            CsvIndexType::F64(index, number_format) => match number_format.parse_float(&key) {
+                Some(k) => index.entry(UnsafeFloat(k)).or_default().push(value),
+                None => index.insert_null(key, value),
+            },
+            CsvIndexType::TIME(index, time_type) => match time_type.parse(&key) {
+                Some(k) => index.entry(k).or_default().push(value),
+                None => index.insert_null(key, value),
+            },
+            CsvIndexType::DECIMAL(index, decimal_type) => {
+                let parsed = decimal::parse(&key);
+                if let Some(scale) = parsed.and_then(|(_, digits)| decimal_type.grow_to(digits)) {
+                    if index.rescale(10i64.pow(scale - decimal_type.scale())) {
+                        decimal_type.set_scale(scale);
+                    }
+                }
+
+                match parsed.and_then(|parsed| decimal_type.key(parsed)) {
+                    Some(k) => index.entry(k).or_default().push(value),
+                    None => index.insert_null(key, value),
+                }
+            }
+            CsvIndexType::UUID(index) => match uuid::parse(&key) {
+                Some(k) => index.entry(k).or_default().push(value),
+                None => index.insert_null(key, value),
+            },
+            CsvIndexType::TRIGRAM(index, normalization) => {
+                let normalized = normalization.key(&key);
+                if normalized.is_empty() {
+                    index.insert_null(vec![], value);
+                } else {
+                    for gram in trigram::trigrams(&normalized) {
+                        index.entry(gram).or_default().push(value.clone());
+                    }
+                }
+            }
+            CsvIndexType::TEXT(index, tokenizer) => {
+                let tokens = tokenizer.keys(&key);
+                if tokens.is_empty() {
+                    index.insert_null(vec![], value);
+                } else {
+                    for token in tokens {
+                        index.entry(token).or_default().push(value.clone());
+                    }
+                }
+            }
+            CsvIndexType::HASH(index, normalization) => {
+                let normalized = normalization.key(&key);
+                if normalized.is_empty() {
+                    index.insert_null(vec![], value);
+                } else {
+                    index
+                        .entry(hash::hash(&normalized))
+                        .or_default()
+                        .push(value);
+                }
             }
         }
     }
 
     pub fn uniques(&self) -> usize {
-        match &self {
-            CsvIndexType::STR(index) => index.uniques(),
-            CsvIndexType::I64(index) => index.uniques(),
-            CsvIndexType::F64(index) => index.uniques(),
-        }
+        each_variant!(CsvIndexType, self, index => index.uniques())
     }
 
     pub fn print_range(&self) {
         match &self {
-            CsvIndexType::STR(index) => {
+            CsvIndexType::STR(index, normalization) => {
+                // on a reversed index these are the values with the first and last suffix
+                let format = |k: &Vec<u8>| {
+                    String::from_utf8(normalization.format_key(k))
+                        .unwrap_or_else(|_| "INVALID".to_owned())
+                };
                 info!(
                     "Min value {:?}, max {:?}",
-                    index
-                        .keys()
-                        .next()
-                        .map(|b| std::str::from_utf8(b).unwrap_or("INVALID")),
-                    index
-                        .keys()
-                        .next_back()
-                        .map(|b| std::str::from_utf8(b).unwrap_or("INVALID")),
+                    index.keys().next().map(format),
+                    index.keys().next_back().map(format),
                 );
             }
-            CsvIndexType::I64(index) => {
+            CsvIndexType::HEX(index) => {
                 info!(
                     "Min value {:?}, max {:?}",
-                    index.keys().find(|&&x| x != i64::MIN),
-                    index.keys().next_back()
+                    index.keys().next().map(|k| format!("{:x}", k)),
+                    index.keys().next_back().map(|k| format!("{:x}", k)),
                 );
             }
-            CsvIndexType::F64(index) => {
+            CsvIndexType::IP(index) => {
                 info!(
                     "Min value {:?}, max {:?}",
-                    index.keys().find(|&&x| x.0 != f64::NEG_INFINITY),
-                    index.keys().next_back()
+                    index.keys().next().map(|k| ip::format(*k)),
+                    index.keys().next_back().map(|k| ip::format(*k)),
+                );
+            }
+            CsvIndexType::UUID(index) => {
+                info!(
+                    "Min value {:?}, max {:?}",
+                    index.keys().next().map(uuid::format),
+                    index.keys().next_back().map(uuid::format),
                 );
             }
+            CsvIndexType::TEXT(index, _) => {
+                info!("{} distinct words", index.uniques());
+            }
+            CsvIndexType::TRIGRAM(index, _) => {
+                info!("{} distinct trigrams", index.uniques());
+            }
+            CsvIndexType::HASH(index, _) => {
+                info!("{} distinct hashes", index.uniques());
+            }
+            CsvIndexType::TIME(index, time_type) => {
+                let format =
+                    |k: &i64| String::from_utf8_lossy(&time_type.format_key(*k)).into_owned();
+                info!(
+                    "Min value {:?}, max {:?}",
+                    index.keys().next().map(format),
+                    index.keys().next_back().map(format)
+                );
+            }
+            CsvIndexType::DECIMAL(index, decimal_type) => {
+                let format = |k: &i64| decimal::format(i128::from(*k), decimal_type.scale());
+                info!(
+                    "Min value {:?}, max {:?} (scale {})",
+                    index.keys().next().map(format),
+                    index.keys().next_back().map(format),
+                    decimal_type.scale()
+                );
+            }
+            // i64, f64, u64, i128 and u128 keys print as they are
+            _ => each_variant!(CsvIndexType, self, index => {
+                info!(
+                    "Min value {:?}, max {:?}",
+                    index.keys().next(),
+                    index.keys().next_back()
+                );
+            }),
         }
     }
 
-    pub fn serialize(self, mut fh: File, length: u64) -> Result<(), Box<Error>> {
-        let num_chunks = 2 + length as usize / 50000;
-        info!("Dividing into {} chunks", num_chunks);
+    pub fn keep_all_samples(&mut self) {
+        each_variant!(CsvIndexType, self, index => index.keep_all_samples())
+    }
 
+    pub fn set_multi_valued(&mut self) -> Result<(), &'static str> {
         match self {
-            CsvIndexType::STR(index) => {
-                let chunked_map = chunk_map(&mut index.into_map(), num_chunks);
-                info!("Writing to file");
+            // matches are checked against the whole value, see hash::print_matches
+            CsvIndexType::HASH(..) => Err("A hash index can not be multi-valued"),
+            _ => {
+                each_variant!(CsvIndexType, self, index => index.set_multi_valued());
+                Ok(())
+            }
+        }
+    }
 
-                let mut toc = Toc::<Vec<u8>>::new(num_chunks);
+    pub fn include_columns(&mut self, columns: Vec<usize>) -> Result<(), &'static str> {
+        match self {
+            // their matches are printed from the csv file, see trigram::print_matches and
+            // hash::print_matches
+            CsvIndexType::TRIGRAM(..) => Err("A trigram index can not include columns"),
+            CsvIndexType::TEXT(..) => Err("A text index can not include columns"),
+            CsvIndexType::HASH(..) => Err("A hash index can not include columns"),
+            _ => {
+                each_variant!(CsvIndexType, self, index => index.include_columns(columns));
+                Ok(())
+            }
+        }
+    }
 
-                // build phantom TOC
-                toc.build_empty(&chunked_map);
+    pub fn include(&mut self, offset: u64, values: Vec<Vec<u8>>) {
+        each_variant!(CsvIndexType, self, index => index.include(offset, values))
+    }
 
-                // write phantom TOC to file, to get the right offsets
-                let typed_toc = TypedToc::STR(toc);
-                typed_toc.write_head(&mut fh, 0)?;
+    pub fn set_sparse(&mut self, block_size: usize) -> Result<(), &'static str> {
+        match self {
+            CsvIndexType::TRIGRAM(..) => Err("A trigram index can not be sparse"),
+            CsvIndexType::TEXT(..) => Err("A text index can not be sparse"),
+            CsvIndexType::HASH(..) => Err("A hash index can not be sparse"),
+            _ => {
+                each_variant!(CsvIndexType, self, index => index.set_sparse(block_size));
+                Ok(())
+            }
+        }
+    }
 
-                // count size of toc
-                let toc_len = fh.seek(SeekFrom::Current(0))?;
+    /// Records without a valid value, not tracked for str indexes
+    pub fn nulls(&self) -> Option<&Nulls> {
+        match self {
+            CsvIndexType::STR(..) => None,
+            _ => Some(each_variant!(CsvIndexType, self, index => index.nulls())),
+        }
+    }
 
-                let mut toc = Toc::<Vec<u8>>::new(num_chunks);
-                toc.write_maps(&mut fh, chunked_map, toc_len)?;
+    pub fn print_nulls(&self) {
+        let nulls = match self.nulls() {
+            Some(nulls) => nulls,
+            None => return,
+        };
 
-                let typed_toc = TypedToc::STR(toc);
-                debug!("TOC {:?}", typed_toc);
-                typed_toc.write_head(&mut fh, toc_len)?;
-            }
+        info!(
+            "{} empty values, {} values failed to parse",
+            nulls.empty, nulls.invalid
+        );
+        nulls
+            .samples
+            .iter()
+            .take(NULL_SAMPLES)
+            .for_each(|(address, raw)| {
+                warn!(
+                    "Unparsable value {:?} in record at byte {}",
+                    String::from_utf8_lossy(raw),
+                    address.offset
+                );
+            });
+    }
+
+    pub fn serialize(self, fh: File, length: u64) -> Result<(), Box<Error>> {
+        let num_chunks = 2 + length as usize / 50000;
+        info!("Dividing into {} chunks", num_chunks);
 
-            CsvIndexType::I64(index) => {
-                let chunked_map = chunk_map(&mut index.into_map(), num_chunks);
-                info!("Writing to file");
+        match self {
+            CsvIndexType::STR(index, normalization) => write_index(index, fh, num_chunks, |toc| {
+                TypedToc::STR(toc, normalization.clone())
+            }),
+            CsvIndexType::I64(index, number_format) => write_index(index, fh, num_chunks, |toc| {
+                TypedToc::I64(toc, number_format.clone())
+            }),
+            CsvIndexType::F64(index, number_format) => write_index(index, fh, num_chunks, |toc| {
+                TypedToc::F64(toc, number_format.clone())
+            }),
+            CsvIndexType::TIME(index, time_type) => write_index(index, fh, num_chunks, |toc| {
+                TypedToc::TIME(toc, time_type.clone())
+            }),
+            CsvIndexType::DECIMAL(index, decimal_type) => {
+                write_index(index, fh, num_chunks, |toc| {
+                    TypedToc::DECIMAL(toc, decimal_type.clone())
+                })
+            }
+            CsvIndexType::U64(index) => write_index(index, fh, num_chunks, TypedToc::U64),
+            CsvIndexType::I128(index) => write_index(index, fh, num_chunks, TypedToc::I128),
+            CsvIndexType::U128(index) => write_index(index, fh, num_chunks, TypedToc::U128),
+            CsvIndexType::HEX(index) => write_index(index, fh, num_chunks, TypedToc::HEX),
+            CsvIndexType::IP(index) => write_index(index, fh, num_chunks, TypedToc::IP),
+            CsvIndexType::UUID(index) => write_index(index, fh, num_chunks, TypedToc::UUID),
+            CsvIndexType::TRIGRAM(index, normalization) => {
+                write_index(index, fh, num_chunks, |toc| {
+                    TypedToc::TRIGRAM(toc, normalization.clone())
+                })
+            }
+            CsvIndexType::TEXT(index, tokenizer) => write_index(index, fh, num_chunks, |toc| {
+                TypedToc::TEXT(toc, tokenizer.clone())
+            }),
+            CsvIndexType::HASH(index, normalization) => {
+                write_chunked(index, fh, num_chunks, chunk_hashes, |toc| {
+                    TypedToc::HASH(toc, normalization.clone())
+                })
+            }
+        }
+    }
+}
 
-                let mut toc = Toc::<i64>::new(num_chunks);
+/// Insert a value that parses with `FromStr`, or record it as null
+fn insert_parsed<R: Ord + FromStr>(index: &mut CsvIndex<R>, key: Vec<u8>, value: Address) {
+    match std::str::from_utf8(&key).ok().and_then(|k| k.parse().ok()) {
+        Some(k) => index.entry(k).or_default().push(value),
+        None => index.insert_null(key, value),
+    }
+}
 
-                // build phantom TOC
-                toc.build_empty(&chunked_map);
+/// Read a hexadecimal integer of at most 128 bits, with or without `0x` prefix
+pub fn parse_hex(value: &[u8]) -> Option<u128> {
+    let value = std::str::from_utf8(value).ok()?.trim();
+    let digits = if value.starts_with("0x") || value.starts_with("0X") {
+        &value[2..]
+    } else {
+        value
+    };
+
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
 
-                // write phantom TOC to file, to get the right offsets
-                let typed_toc = TypedToc::I64(toc);
-                typed_toc.write_head(&mut fh, 0)?;
+    u128::from_str_radix(digits, 16).ok()
+}
 
-                // count size of toc
-                let toc_len = fh.seek(SeekFrom::Current(0))?;
+pub fn format_hex(key: &u128) -> Vec<u8> {
+    format!("{:x}", key).into_bytes()
+}
 
-                let mut toc = Toc::<i64>::new(num_chunks);
-                toc.write_maps(&mut fh, chunked_map, toc_len)?;
+fn write_index<R, F>(
+    index: CsvIndex<R>,
+    fh: File,
+    num_chunks: usize,
+    typed: F,
+) -> Result<(), Box<Error>>
+where
+    R: Ord + Serialize + DeserializeOwned + Clone + Debug,
+    F: Fn(Toc<R>) -> TypedToc,
+{
+    write_chunked(index, fh, num_chunks, chunk_map, typed)
+}
 
-                let typed_toc = TypedToc::I64(toc);
-                debug!("TOC {:?}", typed_toc);
-                typed_toc.write_head(&mut fh, toc_len)?;
+/// Write the index, divided into chapters by the chunk function
+fn write_chunked<R, C, F>(
+    index: CsvIndex<R>,
+    mut fh: File,
+    num_chunks: usize,
+    chunk: C,
+    typed: F,
+) -> Result<(), Box<Error>>
+where
+    R: Ord + Serialize + DeserializeOwned + Clone + Debug,
+    C: FnOnce(&mut BTreeMap<R, Vec<Address>>, usize) -> Vec<(R, BTreeMap<R, Vec<Address>>)>,
+    F: Fn(Toc<R>) -> TypedToc,
+{
+    let multi_valued = index.is_multi_valued();
+    let included_columns = index.included_columns().to_vec();
+    let block_size = index.block_size;
+    let (map, nulls, included) = index.into_parts();
+
+    let (mut map, num_chunks) = match block_size {
+        // the blocks are all read by a query, see sparse::print_matches
+        Some(block_size) => {
+            let blocks = sparse::blocks(map, block_size)?;
+            info!("Storing {} blocks of {} records", blocks.len(), block_size);
+            (blocks, 1)
+        }
+        None => {
+            let records = map.values().map(|addresses| addresses.len()).sum::<usize>();
+            if records > sparse::BLOCK_SIZE && sparse::is_sorted(&map) {
+                info!("The input is sorted by the key, --sorted builds a much smaller index");
             }
-            CsvIndexType::F64(index) => {
-                let chunked_map = chunk_map(&mut index.into_map(), num_chunks);
-                info!("Writing to file");
+            (map, num_chunks)
+        }
+    };
+    let chunked_map = chunk(&mut map, num_chunks);
+    info!("Writing to file");
 
-                let mut toc = Toc::<UnsafeFloat>::new(num_chunks);
+    let sparse = block_size.is_some();
+    let mut toc = Toc::<R>::new(num_chunks, multi_valued, included_columns.clone(), sparse);
 
-                // build phantom TOC
-                toc.build_empty(&chunked_map);
+    // build phantom TOC
+    toc.build_empty(&chunked_map);
 
-                // write phantom TOC to file, to get the right offsets
-                let typed_toc = TypedToc::F64(toc);
-                typed_toc.write_head(&mut fh, 0)?;
+    // write phantom TOC to file, to get the right offsets
+    let typed_toc = typed(toc);
+    typed_toc.write_head(&mut fh, 0)?;
 
-                // count size of toc
-                let toc_len = fh.seek(SeekFrom::Current(0))?;
+    // count size of toc
+    let toc_len = fh.seek(SeekFrom::Current(0))?;
 
-                let mut toc = Toc::<UnsafeFloat>::new(num_chunks);
-                toc.write_maps(&mut fh, chunked_map, toc_len)?;
+    let mut toc = Toc::<R>::new(num_chunks, multi_valued, included_columns, sparse);
+    let nulls_offset = toc.write_maps(&mut fh, chunked_map, &included, toc_len)?;
+    toc.write_nulls(&mut fh, nulls.addresses, &included, nulls_offset)?;
 
-                let typed_toc = TypedToc::F64(toc);
-                debug!("TOC {:?}", typed_toc);
-                typed_toc.write_head(&mut fh, toc_len)?;
-            }
+    let typed_toc = typed(toc);
+    debug!("TOC {:?}", typed_toc);
+    typed_toc.write_head(&mut fh, toc_len)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_null() {
+        let address = |offset| Address { offset, length: 1 };
+
+        let mut index = CsvIndex::<i64>::new();
+        for offset in 0..12 {
+            let raw = if offset % 3 == 0 {
+                vec![]
+            } else {
+                b"x".to_vec()
+            };
+            index.insert_null(raw, address(offset));
+        }
+        let nulls = index.nulls();
+        assert_eq!(nulls.addresses.len(), 12);
+        assert_eq!((nulls.empty, nulls.invalid), (4, 8));
+
+        // only a few unparsable values are kept as examples, empty values are not
+        let offsets: Vec<u64> = nulls
+            .samples
+            .iter()
+            .map(|(address, _)| address.offset)
+            .collect();
+        assert_eq!(offsets, vec![1, 2, 4, 5, 7]);
+
+        let mut index = CsvIndex::<i64>::new();
+        index.keep_all_samples();
+        for offset in 0..12 {
+            index.insert_null(b"x".to_vec(), address(offset));
+        }
+        assert_eq!(index.nulls().samples.len(), 12);
+    }
+
+    #[test]
+    fn test_include_columns() {
+        let include = |csv_type| {
+            let mut index = CsvIndexType::try_new(csv_type, &IndexOptions::default()).unwrap();
+            index.include_columns(vec![1])
         };
+        assert!(include("int").is_ok());
+        assert!(include("str").is_ok());
+        assert!(include("trigram").is_err());
+        assert!(include("text").is_err());
+        assert!(include("hash").is_err());
+    }
 
-        Ok(())
+    #[test]
+    fn test_parse_hex() {
+        assert_eq!(parse_hex(b"ff"), Some(255));
+        assert_eq!(parse_hex(b"0x00FF"), Some(255));
+        assert_eq!(
+            parse_hex(b"ffffffffffffffffffffffffffffffff"),
+            Some(u128::MAX)
+        );
+        assert_eq!(parse_hex(b"1ffffffffffffffffffffffffffffffff"), None);
+        assert_eq!(parse_hex(b"0x"), None);
+        assert_eq!(parse_hex(b"+ff"), None);
+        assert_eq!(parse_hex(b"fg"), None);
     }
 }
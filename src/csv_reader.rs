@@ -1,15 +1,17 @@
 use std::io::BufRead;
 use std::io::BufReader;
+use std::io::Error;
 use std::io::Read;
 use std::io::Seek;
 use std::io::SeekFrom;
 
 use crate::address::Address;
+use crate::expression::Expression;
 use csv::{ByteRecord, ReaderBuilder};
 
 pub struct CsvReader<'a> {
     rdr: csv::Reader<Box<dyn 'a + Read>>,
-    column: usize,
+    key: Expression,
     padding: Vec<u8>,
     offset: u64,
     chunk_size: u64,
@@ -21,18 +23,20 @@ pub struct CsvReader<'a> {
 impl<'a> CsvReader<'a> {
     pub fn new<R: 'a + Read + Seek>(
         mut input: R,
-        column: usize,
+        key: Expression,
         mut offset: u64,
         chunk_size: u64,
-    ) -> Self {
+    ) -> Result<Self, Error> {
         let mut padding = vec![];
 
+        // the input can be shared, e.g. a file handle that was read before
+        input.seek(SeekFrom::Start(offset))?;
+
         let rdr: csv::Reader<Box<dyn Read>> = if offset == 0 {
             ReaderBuilder::new().from_reader(Box::new(input))
         } else {
-            input.seek(SeekFrom::Start(offset)).unwrap(); //todo
             let mut reader = BufReader::with_capacity(1 << 16, input);
-            reader.read_until(10u8, &mut padding).unwrap(); // jump to newline
+            reader.read_until(10u8, &mut padding)?; // jump to newline
             offset += padding.len() as u64;
 
             ReaderBuilder::new()
@@ -40,19 +44,24 @@ impl<'a> CsvReader<'a> {
                 .from_reader(Box::new(reader))
         };
 
-        Self {
+        Ok(Self {
             rdr,
-            column,
+            key,
             padding,
             offset,
             chunk_size,
             record: ByteRecord::new(),
-        }
+        })
     }
 
     pub fn padding(&self) -> &[u8] {
         &self.padding
     }
+
+    /// The record of the last value returned
+    pub fn record(&self) -> &ByteRecord {
+        &self.record
+    }
 }
 
 impl<'a> Iterator for CsvReader<'a> {
@@ -74,14 +83,41 @@ impl<'a> Iterator for CsvReader<'a> {
             length: self.rdr.position().byte() - pos,
         };
 
-        Some((address, self.record[self.column].to_owned()))
+        // records without the column(s) of the key are indexed as empty values
+        let value = self.key.eval(&self.record).unwrap_or_default();
+
+        Some((address, value))
     }
 }
 
+/// A single csv record, as read from an `Address`
+pub fn parse_record(record: &[u8]) -> Option<ByteRecord> {
+    let mut rdr = ReaderBuilder::new().has_headers(false).from_reader(record);
+    let mut parsed = ByteRecord::new();
+    match rdr.read_byte_record(&mut parsed) {
+        Ok(true) => Some(parsed),
+        _ => None,
+    }
+}
+
+/// Value of a column in a single csv record, as read from an `Address`
+pub fn parse_field(record: &[u8], column: usize) -> Option<Vec<u8>> {
+    parse_record(record)?
+        .get(column)
+        .map(|field| field.to_owned())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_parse_field() {
+        let record = b"Boston,\"United States, MA\",4628910\n";
+        assert_eq!(parse_field(record, 1), Some(b"United States, MA".to_vec()));
+        assert_eq!(parse_field(record, 3), None);
+    }
+
     #[test]
     fn test_read_full() {
         let input = std::io::Cursor::new(
@@ -91,7 +127,7 @@ Boston,United States,4628910
 Amsterdam,Netherlands,7500000
 ",
         );
-        let mut reader = CsvReader::new(input, 0, 0, 1000);
+        let mut reader = CsvReader::new(input, Expression::Column(0), 0, 1000).unwrap();
 
         assert_eq!(reader.padding(), vec![].as_slice());
 
@@ -134,7 +170,7 @@ Boston,United States,4628910
 Amsterdam,Netherlands,7500000
 ",
         );
-        let mut reader = CsvReader::new(input, 0, 0, 40);
+        let mut reader = CsvReader::new(input, Expression::Column(0), 0, 40).unwrap();
 
         assert_eq!(reader.padding(), vec![].as_slice());
 
@@ -164,7 +200,7 @@ Boston,United States,4628910
 Amsterdam,Netherlands,7500000
 ",
         );
-        let mut reader = CsvReader::new(input, 0, 25, 1000);
+        let mut reader = CsvReader::new(input, Expression::Column(0), 25, 1000).unwrap();
 
         assert_eq!(
             std::str::from_utf8(reader.padding()),
@@ -0,0 +1,593 @@
+use serde::{Deserialize, Serialize};
+
+const MILLIS_PER_DAY: i64 = 86_400_000;
+
+const MONTHS: [&str; 12] = [
+    "JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC",
+];
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum TimeKind {
+    /// Calendar date, keyed by days since 1970-01-01
+    DATE,
+    /// Date and time, keyed by milliseconds since 1970-01-01T00:00:00Z
+    DATETIME,
+    /// Like DATETIME, but read from epoch seconds by default
+    TIMESTAMP,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum TimeFormat {
+    /// 2026-01-31, 2026-01-31T12:00:00, 2026-01-31 12:00:00.250+01:00, ..
+    ISO,
+    /// Sat, 31 Jan 2026 12:00:00 +0100
+    RFC2822,
+    /// Seconds since 1970-01-01T00:00:00Z
+    EPOCH,
+    /// Milliseconds since 1970-01-01T00:00:00Z
+    EPOCHMS,
+    /// strftime-like pattern, supporting %Y %y %m %b %d %H %M %S %f %z and %%
+    PATTERN(String),
+}
+
+impl TimeFormat {
+    pub fn from(format: &str) -> Self {
+        match format.to_uppercase().as_ref() {
+            "ISO" => TimeFormat::ISO,
+            "RFC2822" => TimeFormat::RFC2822,
+            "EPOCH" => TimeFormat::EPOCH,
+            "EPOCH-MS" => TimeFormat::EPOCHMS,
+            _ => TimeFormat::PATTERN(format.to_owned()),
+        }
+    }
+}
+
+/// A point in time as read from the input, before applying a timezone
+#[derive(Debug, PartialEq)]
+struct Civil {
+    days: i64,
+    millis: i64,
+    /// offset east of UTC in seconds, if present in the value
+    offset: Option<i64>,
+}
+
+/// How to read and write the keys of a date, datetime or timestamp index
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TimeType {
+    kind: TimeKind,
+    format: TimeFormat,
+    /// offset east of UTC in seconds, for values without an explicit offset
+    tz: i64,
+}
+
+impl TimeType {
+    pub fn try_new(
+        csv_type: &str,
+        format: Option<&str>,
+        tz: Option<&str>,
+    ) -> Result<Option<Self>, &'static str> {
+        let kind = match csv_type.to_uppercase().as_ref() {
+            "DATE" => TimeKind::DATE,
+            "DATETIME" => TimeKind::DATETIME,
+            "TIMESTAMP" => TimeKind::TIMESTAMP,
+            _ => return Ok(None),
+        };
+
+        let format = match (format, kind) {
+            (Some(format), _) => TimeFormat::from(format),
+            (None, TimeKind::TIMESTAMP) => TimeFormat::EPOCH,
+            (None, _) => TimeFormat::ISO,
+        };
+
+        let tz = match tz {
+            Some(tz) => {
+                parse_tz(tz).ok_or("Invalid timezone, use UTC or a fixed offset like +02:00")?
+            }
+            None => 0,
+        };
+
+        Ok(Some(TimeType { kind, format, tz }))
+    }
+
+    /// Key of a csv value in the format of this index
+    pub fn parse(&self, raw: &[u8]) -> Option<i64> {
+        let value = std::str::from_utf8(raw).ok()?.trim();
+        self.key(parse_civil(value, &self.format)?)
+    }
+
+    /// Key of a query value, in the format of this index or ISO 8601
+    pub fn parse_value(&self, value: &str) -> Option<i64> {
+        let value = value.trim();
+        let civil = parse_civil(value, &self.format).or_else(|| parse_iso(value))?;
+        self.key(civil)
+    }
+
+    /// None for instants too far from 1970 to key in milliseconds, like other unparsable values
+    fn key(&self, civil: Civil) -> Option<i64> {
+        if let (TimeKind::DATE, None) = (self.kind, civil.offset) {
+            return Some(civil.days);
+        }
+
+        let millis = civil
+            .days
+            .checked_mul(MILLIS_PER_DAY)?
+            .checked_add(civil.millis)?;
+        match (self.kind, civil.offset) {
+            (TimeKind::DATE, Some(offset)) => {
+                // the local date of this instant
+                let local = millis.checked_add((self.tz - offset) * 1000)?;
+                Some(local.div_euclid(MILLIS_PER_DAY))
+            }
+            (_, offset) => {
+                let key = millis.checked_sub(offset.unwrap_or(self.tz) * 1000)?;
+                // format_key shows the key in local time
+                key.checked_add(self.tz * 1000)?;
+                Some(key)
+            }
+        }
+    }
+
+    pub fn format_key(&self, key: i64) -> Vec<u8> {
+        if self.kind == TimeKind::DATE {
+            let (y, m, d) = civil_from_days(key);
+            return format!("{:04}-{:02}-{:02}", y, m, d).into_bytes();
+        }
+
+        let local = match key.checked_add(self.tz * 1000) {
+            Some(local) => local,
+            None => return key.to_string().into_bytes(),
+        };
+        let (y, m, d) = civil_from_days(local.div_euclid(MILLIS_PER_DAY));
+        let millis = local.rem_euclid(MILLIS_PER_DAY);
+
+        let mut out = format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            y,
+            m,
+            d,
+            millis / 3_600_000,
+            millis / 60_000 % 60,
+            millis / 1000 % 60
+        );
+        if millis % 1000 != 0 {
+            out.push_str(&format!(".{:03}", millis % 1000));
+        }
+        if self.tz == 0 {
+            out.push('Z');
+        } else {
+            let sign = if self.tz < 0 { '-' } else { '+' };
+            let tz = self.tz.abs();
+            out.push_str(&format!("{}{:02}:{:02}", sign, tz / 3600, tz / 60 % 60));
+        }
+
+        out.into_bytes()
+    }
+}
+
+fn parse_civil(value: &str, format: &TimeFormat) -> Option<Civil> {
+    match format {
+        TimeFormat::ISO => parse_iso(value),
+        TimeFormat::RFC2822 => parse_rfc2822(value),
+        TimeFormat::EPOCH => {
+            let millis = (value.parse::<f64>().ok()? * 1000.).round();
+            // `as` saturates and maps nan to 0, so check the value fits first
+            if !millis.is_finite() || millis < i64::MIN as f64 || millis >= i64::MAX as f64 {
+                return None;
+            }
+            from_epoch_millis(millis as i64)
+        }
+        TimeFormat::EPOCHMS => from_epoch_millis(value.parse().ok()?),
+        TimeFormat::PATTERN(pattern) => parse_pattern(value, pattern),
+    }
+}
+
+fn from_epoch_millis(millis: i64) -> Option<Civil> {
+    Some(Civil {
+        days: millis.div_euclid(MILLIS_PER_DAY),
+        millis: millis.rem_euclid(MILLIS_PER_DAY),
+        offset: Some(0),
+    })
+}
+
+/// Build a civil time from its fields, checking they are in range
+fn civil(
+    (year, month, day): (i64, u32, u32),
+    (hour, minute, second, millis): (u32, u32, u32, u32),
+    offset: Option<i64>,
+) -> Option<Civil> {
+    if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+        return None;
+    }
+    if hour > 23 || minute > 59 || second > 60 || millis > 999 {
+        return None;
+    }
+
+    Some(Civil {
+        days: days_from_civil(year, month, day),
+        millis: i64::from(((hour * 60 + minute) * 60 + second) * 1000 + millis),
+        offset,
+    })
+}
+
+/// Reads the ISO 8601 subset `YYYY-MM-DD[(T| )HH:MM[:SS[.fff]]][Z|±HH[:MM]]`
+fn parse_iso(value: &str) -> Option<Civil> {
+    let mut scanner = Scanner::new(value);
+
+    let year = scanner.number(4, 4)? as i64;
+    scanner.literal("-")?;
+    let month = scanner.number(2, 2)?;
+    scanner.literal("-")?;
+    let day = scanner.number(2, 2)?;
+
+    let mut time = (0, 0, 0, 0);
+    if scanner
+        .literal("T")
+        .or_else(|| scanner.literal(" "))
+        .is_some()
+    {
+        time.0 = scanner.number(2, 2)?;
+        scanner.literal(":")?;
+        time.1 = scanner.number(2, 2)?;
+        if scanner.literal(":").is_some() {
+            time.2 = scanner.number(2, 2)?;
+            if scanner
+                .literal(".")
+                .or_else(|| scanner.literal(","))
+                .is_some()
+            {
+                time.3 = scanner.fraction()?;
+            }
+        }
+    }
+
+    let offset = if scanner.is_empty() {
+        None
+    } else {
+        Some(scanner.offset()?)
+    };
+
+    if !scanner.is_empty() {
+        return None;
+    }
+
+    civil((year, month, day), time, offset)
+}
+
+/// Reads RFC 2822 dates like `Sat, 31 Jan 2026 12:00:00 +0100`
+fn parse_rfc2822(value: &str) -> Option<Civil> {
+    // the day of week is optional and redundant
+    let value = match value.find(',') {
+        Some(pos) => &value[pos + 1..],
+        None => value,
+    };
+    let mut parts = value.split_whitespace();
+
+    let day = parts.next()?.parse().ok()?;
+    let month = month_from_name(parts.next()?)?;
+    // at most 5 digits, so the days since 1970 can not overflow
+    let year = i64::from(parts.next()?.parse::<u16>().ok()?);
+
+    let mut time = parts.next()?.split(':');
+    let hour = time.next()?.parse().ok()?;
+    let minute = time.next()?.parse().ok()?;
+    let second = time.next().map(|s| s.parse().ok()).unwrap_or(Some(0))?;
+    if time.next().is_some() {
+        return None;
+    }
+
+    let offset = match parts.next()? {
+        "UT" | "GMT" | "Z" => 0,
+        "EST" => -5 * 3600,
+        "EDT" => -4 * 3600,
+        "CST" => -6 * 3600,
+        "CDT" => -5 * 3600,
+        "MST" => -7 * 3600,
+        "MDT" => -6 * 3600,
+        "PST" => -8 * 3600,
+        "PDT" => -7 * 3600,
+        zone => parse_tz(zone)?,
+    };
+    if parts.next().is_some() {
+        return None;
+    }
+
+    civil((year, month, day), (hour, minute, second, 0), Some(offset))
+}
+
+/// Reads a value according to a strftime-like pattern
+fn parse_pattern(value: &str, pattern: &str) -> Option<Civil> {
+    let mut scanner = Scanner::new(value);
+
+    let (mut year, mut month, mut day) = (1970, 1, 1);
+    let mut time = (0, 0, 0, 0);
+    let mut offset = None;
+
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            scanner.literal(&c.to_string())?;
+            continue;
+        }
+
+        match chars.next()? {
+            'Y' => year = scanner.number(4, 4)? as i64,
+            'y' => {
+                // same pivot as POSIX strptime: 69-99 is 1969-1999
+                let y = scanner.number(2, 2)? as i64;
+                year = if y < 69 { 2000 + y } else { 1900 + y };
+            }
+            'm' => month = scanner.number(1, 2)?,
+            'b' => month = scanner.month_name()?,
+            'd' => day = scanner.number(1, 2)?,
+            'H' => time.0 = scanner.number(1, 2)?,
+            'M' => time.1 = scanner.number(1, 2)?,
+            'S' => time.2 = scanner.number(1, 2)?,
+            'f' => time.3 = scanner.fraction()?,
+            'z' => offset = Some(scanner.offset()?),
+            '%' => scanner.literal("%")?,
+            _ => return None,
+        }
+    }
+
+    if !scanner.is_empty() {
+        return None;
+    }
+
+    civil((year, month, day), time, offset)
+}
+
+/// Offset east of UTC in seconds, from `UTC`, `Z`, `+02:00`, `+0200` or `+02`
+fn parse_tz(tz: &str) -> Option<i64> {
+    if tz.eq_ignore_ascii_case("UTC") {
+        return Some(0);
+    }
+
+    let mut scanner = Scanner::new(tz);
+    let offset = scanner.offset()?;
+    if !scanner.is_empty() {
+        return None;
+    }
+
+    Some(offset)
+}
+
+fn month_from_name(name: &str) -> Option<u32> {
+    let name = name.get(..3)?.to_uppercase();
+    MONTHS
+        .iter()
+        .position(|&m| m == name)
+        .map(|pos| pos as u32 + 1)
+}
+
+struct Scanner<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Scanner<'a> {
+    fn new(value: &'a str) -> Self {
+        Scanner { rest: value }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.rest.is_empty()
+    }
+
+    fn literal(&mut self, literal: &str) -> Option<()> {
+        if self.rest.starts_with(literal) {
+            self.rest = &self.rest[literal.len()..];
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    fn digits(&mut self, min: usize, max: usize) -> Option<&'a str> {
+        let len = self
+            .rest
+            .bytes()
+            .take(max)
+            .take_while(u8::is_ascii_digit)
+            .count();
+        if len < min {
+            return None;
+        }
+
+        let digits = &self.rest[..len];
+        self.rest = &self.rest[len..];
+        Some(digits)
+    }
+
+    fn number(&mut self, min: usize, max: usize) -> Option<u32> {
+        self.digits(min, max)?.parse().ok()
+    }
+
+    /// Fractional seconds as milliseconds, ignoring digits beyond millisecond precision
+    fn fraction(&mut self) -> Option<u32> {
+        let digits = self.digits(1, usize::MAX)?;
+        let millis = format!("{:0<3}", &digits[..digits.len().min(3)]);
+        millis.parse().ok()
+    }
+
+    fn month_name(&mut self) -> Option<u32> {
+        let month = month_from_name(self.rest)?;
+        self.rest = &self.rest[3..];
+        Some(month)
+    }
+
+    /// `Z`, `±HH:MM`, `±HHMM` or `±HH`, in seconds east of UTC
+    fn offset(&mut self) -> Option<i64> {
+        if self.literal("Z").is_some() {
+            return Some(0);
+        }
+
+        let sign = if self.literal("+").is_some() {
+            1
+        } else if self.literal("-").is_some() {
+            -1
+        } else {
+            return None;
+        };
+
+        let hours = self.number(2, 2)?;
+        self.literal(":");
+        let minutes = self.number(2, 2).unwrap_or(0);
+        if hours > 23 || minutes > 59 {
+            return None;
+        }
+
+        Some(sign * i64::from(hours * 3600 + minutes * 60))
+    }
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let month = i64::from(month);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+
+    era * 146_097 + doe - 719_468
+}
+
+/// Inverse of days_from_civil
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let doe = days - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time_type(csv_type: &str, format: Option<&str>, tz: Option<&str>) -> TimeType {
+        TimeType::try_new(csv_type, format, tz).unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_civil_days() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+
+        for days in -800_000..800_000 {
+            let (y, m, d) = civil_from_days(days);
+            assert_eq!(days_from_civil(y, m, d), days);
+        }
+    }
+
+    #[test]
+    fn test_not_a_time_type() {
+        assert!(TimeType::try_new("int", None, None).unwrap().is_none());
+        assert!(TimeType::try_new("date", None, Some("Europe/Amsterdam")).is_err());
+    }
+
+    #[test]
+    fn test_date_iso() {
+        let date = time_type("date", None, None);
+        assert_eq!(date.parse(b"1970-01-02"), Some(1));
+        assert_eq!(date.parse(b"2026-02-29"), None);
+        assert_eq!(date.parse(b"2024-02-29"), Some(19782));
+        assert_eq!(date.parse(b"2024-02-29 23:59:59"), Some(19782));
+        assert_eq!(date.parse(b"20240229"), None);
+        assert_eq!(date.format_key(19782), b"2024-02-29".to_vec());
+    }
+
+    #[test]
+    fn test_date_pattern() {
+        let date = time_type("date", Some("%d/%m/%Y"), None);
+        assert_eq!(date.parse(b"29/02/2024"), Some(19782));
+        assert_eq!(date.parse(b"2024-02-29"), None);
+
+        // query values may also be written as ISO
+        assert_eq!(date.parse_value("2024-02-29"), Some(19782));
+        assert_eq!(date.parse_value("1/3/2024"), Some(19783));
+    }
+
+    #[test]
+    fn test_datetime_offsets() {
+        let utc = time_type("datetime", None, None);
+        assert_eq!(utc.parse(b"1970-01-01T00:00:01"), Some(1000));
+        assert_eq!(utc.parse(b"1970-01-01T01:00:00+01:00"), Some(0));
+        assert_eq!(utc.parse(b"1970-01-01 00:00:00.5Z"), Some(500));
+        assert_eq!(utc.format_key(1500), b"1970-01-01T00:00:01.500Z".to_vec());
+
+        let cet = time_type("datetime", None, Some("+01:00"));
+        assert_eq!(cet.parse(b"1970-01-01T01:00:00"), Some(0));
+        assert_eq!(cet.parse(b"1970-01-01T01:00:00Z"), Some(3_600_000));
+        assert_eq!(cet.format_key(0), b"1970-01-01T01:00:00+01:00".to_vec());
+    }
+
+    #[test]
+    fn test_rfc2822() {
+        let datetime = time_type("datetime", Some("rfc2822"), None);
+        assert_eq!(datetime.parse(b"Thu, 01 Jan 1970 01:00:00 +0100"), Some(0));
+        assert_eq!(datetime.parse(b"1 Jan 1970 00:00 GMT"), Some(0));
+        assert_eq!(datetime.parse(b"1 Jan 1970 00:00:00 EST"), Some(18_000_000));
+        assert_eq!(datetime.parse(b"1 Foo 1970 00:00:00 GMT"), None);
+    }
+
+    #[test]
+    fn test_timestamp() {
+        let seconds = time_type("timestamp", None, None);
+        assert_eq!(seconds.parse(b"1700000000"), Some(1_700_000_000_000));
+        assert_eq!(seconds.parse(b"-1.5"), Some(-1500));
+        assert_eq!(seconds.parse(b"nan"), None);
+        assert_eq!(seconds.parse(b"inf"), None);
+        assert_eq!(seconds.parse(b"-1e300"), None);
+        assert_eq!(
+            seconds.parse_value("2023-11-14T22:13:20Z"),
+            Some(1_700_000_000_000)
+        );
+
+        let millis = time_type("timestamp", Some("epoch-ms"), None);
+        assert_eq!(millis.parse(b"1700000000123"), Some(1_700_000_000_123));
+
+        // epoch values are UTC instants, a date index uses the local date
+        let date = time_type("date", Some("epoch"), Some("-05:00"));
+        assert_eq!(date.parse(b"3600"), Some(-1));
+    }
+
+    #[test]
+    fn test_overflow() {
+        // the largest epoch-ms values can not be shown in a timezone east of UTC
+        let cest = time_type("timestamp", Some("epoch-ms"), Some("+02:00"));
+        assert_eq!(cest.parse(b"9223372036854775807"), None);
+        assert_eq!(
+            cest.parse(b"-9223372036800000000"),
+            Some(-9_223_372_036_800_000_000)
+        );
+
+        let pst = time_type("timestamp", Some("epoch-ms"), Some("-08:00"));
+        assert_eq!(pst.parse(b"-9223372036854775808"), None);
+
+        let date = time_type("date", Some("epoch-ms"), Some("+02:00"));
+        assert_eq!(date.parse(b"9223372036854775807"), None);
+
+        let rfc = time_type("datetime", Some("rfc2822"), None);
+        assert_eq!(rfc.parse(b"1 Jan 9223372036854775807 00:00 Z"), None);
+    }
+}
@@ -0,0 +1,182 @@
+use serde::{Deserialize, Serialize};
+
+/// Most fraction digits a decimal index can hold, more would overflow an i64 quickly
+const MAX_SCALE: u32 = 18;
+
+/// Exact decimals, keyed by the value multiplied by 10^scale
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DecimalType {
+    scale: u32,
+
+    // grow the scale to fit the values found while indexing
+    #[serde(skip)]
+    auto: bool,
+}
+
+impl DecimalType {
+    pub fn try_new(scale: Option<u32>) -> Result<Self, &'static str> {
+        match scale {
+            Some(scale) if scale > MAX_SCALE => Err("Decimal scale can be at most 18"),
+            Some(scale) => Ok(DecimalType { scale, auto: false }),
+            None => Ok(DecimalType {
+                scale: 0,
+                auto: true,
+            }),
+        }
+    }
+
+    pub fn scale(&self) -> u32 {
+        self.scale
+    }
+
+    /// When auto detecting, the scale to switch to for a value with this many fraction digits
+    pub fn grow_to(&self, digits: u32) -> Option<u32> {
+        if self.auto && digits > self.scale && digits <= MAX_SCALE {
+            Some(digits)
+        } else {
+            None
+        }
+    }
+
+    pub fn set_scale(&mut self, scale: u32) {
+        self.scale = scale;
+    }
+
+    /// Key of a csv value, if it can be represented exactly at the scale of this index
+    pub fn key(&self, (mantissa, digits): (i64, u32)) -> Option<i64> {
+        if digits <= self.scale {
+            mantissa.checked_mul(10i64.checked_pow(self.scale - digits)?)
+        } else {
+            let divisor = 10i64.checked_pow(digits - self.scale)?;
+            if mantissa % divisor == 0 {
+                Some(mantissa / divisor)
+            } else {
+                None
+            }
+        }
+    }
+
+    /// The largest key at or below this value, and the smallest key at or above it
+    pub fn floor_ceil(&self, (mantissa, digits): (i64, u32)) -> Option<(i64, i64)> {
+        if digits <= self.scale {
+            let key = self.key((mantissa, digits))?;
+            return Some((key, key));
+        }
+
+        let divisor = 10i64.checked_pow(digits - self.scale)?;
+        let floor = mantissa.div_euclid(divisor);
+        let ceil = if mantissa.rem_euclid(divisor) == 0 {
+            floor
+        } else {
+            floor + 1
+        };
+
+        Some((floor, ceil))
+    }
+
+    pub fn format_key(&self, key: i64) -> Vec<u8> {
+        format(i128::from(key), self.scale).into_bytes()
+    }
+}
+
+/// Read a decimal like `-12.50` into its digits without the point, and the number of fraction digits
+pub fn parse(value: &[u8]) -> Option<(i64, u32)> {
+    let value = std::str::from_utf8(value).ok()?.trim();
+
+    let (negative, value) = match value.as_bytes().first()? {
+        b'-' => (true, &value[1..]),
+        b'+' => (false, &value[1..]),
+        _ => (false, value),
+    };
+
+    let (int, fraction) = match value.find('.') {
+        Some(pos) => (&value[..pos], &value[pos + 1..]),
+        None => (value, ""),
+    };
+    if int.is_empty() && fraction.is_empty() {
+        return None;
+    }
+    if !int
+        .bytes()
+        .chain(fraction.bytes())
+        .all(|b| b.is_ascii_digit())
+    {
+        return None;
+    }
+
+    // trailing zeros do not add precision
+    let fraction = fraction.trim_end_matches('0');
+
+    let mut mantissa: i64 = 0;
+    for b in int.bytes().chain(fraction.bytes()) {
+        mantissa = mantissa.checked_mul(10)?.checked_add(i64::from(b - b'0'))?;
+    }
+    if negative {
+        mantissa = -mantissa;
+    }
+
+    Some((mantissa, fraction.len() as u32))
+}
+
+/// Write a scaled integer as decimal, e.g. (-5, 2) becomes -0.05
+pub fn format(key: i128, scale: u32) -> String {
+    let digits = key.abs().to_string();
+    let sign = if key < 0 { "-" } else { "" };
+
+    if scale == 0 {
+        return format!("{}{}", sign, digits);
+    }
+
+    let digits = format!("{:0>width$}", digits, width = scale as usize + 1);
+    let (int, fraction) = digits.split_at(digits.len() - scale as usize);
+    format!("{}{}.{}", sign, int, fraction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse(b"19.99"), Some((1999, 2)));
+        assert_eq!(parse(b"-0.050"), Some((-5, 2)));
+        assert_eq!(parse(b"+7"), Some((7, 0)));
+        assert_eq!(parse(b".5"), Some((5, 1)));
+        assert_eq!(parse(b"5."), Some((5, 0)));
+        assert_eq!(parse(b"."), None);
+        assert_eq!(parse(b""), None);
+        assert_eq!(parse(b"1e5"), None);
+        assert_eq!(parse(b"1.2.3"), None);
+        assert_eq!(parse(b"99999999999999999999"), None);
+    }
+
+    #[test]
+    fn test_key() {
+        let decimal = DecimalType::try_new(Some(2)).unwrap();
+        assert_eq!(decimal.key((1999, 2)), Some(1999));
+        assert_eq!(decimal.key((5, 0)), Some(500));
+        assert_eq!(decimal.key((19995, 3)), None);
+        assert_eq!(decimal.floor_ceil((19995, 3)), Some((1999, 2000)));
+        assert_eq!(decimal.floor_ceil((-19995, 3)), Some((-2000, -1999)));
+    }
+
+    #[test]
+    fn test_grow() {
+        let mut decimal = DecimalType::try_new(None).unwrap();
+        assert_eq!(decimal.grow_to(2), Some(2));
+        decimal.set_scale(2);
+        assert_eq!(decimal.grow_to(1), None);
+        assert_eq!(decimal.grow_to(19), None);
+
+        let fixed = DecimalType::try_new(Some(2)).unwrap();
+        assert_eq!(fixed.grow_to(3), None);
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format(1999, 2), "19.99");
+        assert_eq!(format(-5, 2), "-0.05");
+        assert_eq!(format(500, 0), "500");
+        assert_eq!(format(0, 3), "0.000");
+    }
+}
@@ -0,0 +1,396 @@
+use crate::csv_reader;
+
+use csv::ByteRecord;
+use regex::bytes::Regex;
+
+use std::error::Error;
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Unit {
+    YEAR,
+    MONTH,
+    DAY,
+}
+
+/// The key a record is indexed under: a column, or a value derived from one or more columns
+#[derive(Clone, Debug)]
+pub enum Expression {
+    /// Column number, starting at 0
+    Column(usize),
+    Literal(String),
+    Lower(Box<Expression>),
+    /// Characters from a position (starting at 1), up to a length
+    Substr(Box<Expression>, usize, Option<usize>),
+    /// First capture group of a regex, or the whole match without groups
+    Capture(Box<Expression>, Regex),
+    /// Year, month or day of an ISO 8601 date or datetime
+    Trunc(Box<Expression>, Unit),
+    Hash(Box<Expression>),
+    Concat(Vec<Expression>),
+}
+
+/// Argument of a function, before it is known how the function uses it
+enum Arg {
+    Number(usize),
+    Text(String),
+    Call(Expression),
+}
+
+impl Arg {
+    fn into_expression(self) -> Result<Expression, Box<dyn Error>> {
+        match self {
+            Arg::Number(0) => Err("Column numbers start at 1")?,
+            Arg::Number(column) => Ok(Expression::Column(column - 1)),
+            Arg::Text(text) => Ok(Expression::Literal(text)),
+            Arg::Call(expression) => Ok(expression),
+        }
+    }
+}
+
+impl Expression {
+    /// Parse a column number (starting at 1), or an expression like `lower(regex(4,'@(.+)$'))`
+    pub fn parse(text: &str) -> Result<Self, Box<dyn Error>> {
+        let mut chars = text.chars().peekable();
+        let expression = parse_arg(&mut chars)?.into_expression()?;
+
+        skip_whitespace(&mut chars);
+        match chars.next() {
+            None => Ok(expression),
+            Some(c) => Err(format!("Unexpected {:?} in key expression {:?}", c, text))?,
+        }
+    }
+
+    /// Key of a csv record, or None when it has no such value
+    pub fn eval(&self, record: &ByteRecord) -> Option<Vec<u8>> {
+        match self {
+            Expression::Column(column) => record.get(*column).map(|field| field.to_vec()),
+            Expression::Literal(text) => Some(text.as_bytes().to_vec()),
+            Expression::Lower(inner) => inner.eval(record).map(|value| lower(&value)),
+            Expression::Substr(inner, from, len) => {
+                inner.eval(record).map(|value| substr(&value, *from, *len))
+            }
+            Expression::Capture(inner, regex) => {
+                inner.eval(record).and_then(|value| capture(regex, &value))
+            }
+            Expression::Trunc(inner, unit) => {
+                inner.eval(record).and_then(|value| truncate(&value, *unit))
+            }
+            Expression::Hash(inner) => inner
+                .eval(record)
+                .map(|value| format!("{:016x}", fnv1a(&value)).into_bytes()),
+            Expression::Concat(parts) => parts
+                .iter()
+                .map(|part| part.eval(record))
+                .collect::<Option<Vec<_>>>()
+                .map(|values| values.concat()),
+        }
+    }
+
+    /// Key of a single raw csv record, as read from an `Address`
+    pub fn eval_raw(&self, record: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            Expression::Column(column) => csv_reader::parse_field(record, *column),
+            _ => csv_reader::parse_record(record).and_then(|record| self.eval(&record)),
+        }
+    }
+
+    /// File name of the index on this key
+    pub fn index_path(&self, filename: &str) -> String {
+        match self {
+            Expression::Column(column) => format!("{}.index.{}", filename, column + 1),
+            _ => format!("{}.index.{}", filename, escape(&self.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expression::Column(column) => write!(f, "{}", column + 1),
+            Expression::Literal(text) => write!(f, "{}", quote(text)),
+            Expression::Lower(inner) => write!(f, "lower({})", inner),
+            Expression::Substr(inner, from, None) => write!(f, "substr({},{})", inner, from),
+            Expression::Substr(inner, from, Some(len)) => {
+                write!(f, "substr({},{},{})", inner, from, len)
+            }
+            Expression::Capture(inner, regex) => {
+                write!(f, "regex({},{})", inner, quote(regex.as_str()))
+            }
+            Expression::Trunc(inner, Unit::YEAR) => write!(f, "year({})", inner),
+            Expression::Trunc(inner, Unit::MONTH) => write!(f, "month({})", inner),
+            Expression::Trunc(inner, Unit::DAY) => write!(f, "day({})", inner),
+            Expression::Hash(inner) => write!(f, "hash({})", inner),
+            Expression::Concat(parts) => {
+                let parts: Vec<String> = parts.iter().map(|part| part.to_string()).collect();
+                write!(f, "concat({})", parts.join(","))
+            }
+        }
+    }
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+    while chars.peek().is_some_and(|c| c.is_whitespace()) {
+        chars.next();
+    }
+}
+
+fn parse_arg(chars: &mut Peekable<Chars>) -> Result<Arg, Box<dyn Error>> {
+    skip_whitespace(chars);
+
+    match chars.peek().cloned() {
+        Some(c) if c.is_ascii_digit() => {
+            let mut digits = String::new();
+            while let Some(c) = chars.peek().cloned().filter(|c| c.is_ascii_digit()) {
+                digits.push(c);
+                chars.next();
+            }
+            Ok(Arg::Number(digits.parse()?))
+        }
+        Some('\'') => {
+            chars.next();
+            let mut text = String::new();
+            loop {
+                match chars.next() {
+                    // a quote is escaped by doubling it
+                    Some('\'') if chars.peek() == Some(&'\'') => {
+                        chars.next();
+                        text.push('\'');
+                    }
+                    Some('\'') => return Ok(Arg::Text(text)),
+                    Some(c) => text.push(c),
+                    None => Err("Unterminated quote in key expression")?,
+                }
+            }
+        }
+        Some(c) if c.is_ascii_alphabetic() => {
+            let mut name = String::new();
+            while let Some(c) = chars.peek().cloned().filter(|c| c.is_ascii_alphabetic()) {
+                name.push(c);
+                chars.next();
+            }
+
+            skip_whitespace(chars);
+            if chars.next() != Some('(') {
+                Err(format!("Expected ( after {} in key expression", name))?
+            }
+
+            let mut args = vec![];
+            loop {
+                args.push(parse_arg(chars)?);
+                skip_whitespace(chars);
+                match chars.next() {
+                    Some(',') => (),
+                    Some(')') => break,
+                    _ => Err(format!("Expected , or ) in arguments of {}", name))?,
+                }
+            }
+
+            Ok(Arg::Call(call(&name.to_lowercase(), args)?))
+        }
+        _ => Err("Expected a column number, 'text' or function in key expression")?,
+    }
+}
+
+fn call(name: &str, args: Vec<Arg>) -> Result<Expression, Box<dyn Error>> {
+    let mut args = args.into_iter();
+    let mut next_expression = || -> Result<Box<Expression>, Box<dyn Error>> {
+        match args.next() {
+            Some(arg) => Ok(Box::new(arg.into_expression()?)),
+            None => Err(format!("{} needs a column or expression", name))?,
+        }
+    };
+
+    let expression = match name {
+        "lower" => Expression::Lower(next_expression()?),
+        "hash" => Expression::Hash(next_expression()?),
+        "year" => Expression::Trunc(next_expression()?, Unit::YEAR),
+        "month" => Expression::Trunc(next_expression()?, Unit::MONTH),
+        "day" => Expression::Trunc(next_expression()?, Unit::DAY),
+        "substr" => {
+            let inner = next_expression()?;
+            let from = match args.next() {
+                Some(Arg::Number(from)) if from > 0 => from,
+                _ => Err("substr needs a position, starting at 1")?,
+            };
+            let len = match args.next() {
+                None => None,
+                Some(Arg::Number(len)) => Some(len),
+                Some(_) => Err("The length of substr should be a number")?,
+            };
+            Expression::Substr(inner, from, len)
+        }
+        "regex" => {
+            let inner = next_expression()?;
+            let regex = match args.next() {
+                Some(Arg::Text(pattern)) => Regex::new(&pattern)?,
+                _ => Err("regex needs a 'pattern'")?,
+            };
+            Expression::Capture(inner, regex)
+        }
+        "concat" => {
+            let parts = args
+                .map(|arg| arg.into_expression())
+                .collect::<Result<Vec<_>, _>>()?;
+            return Ok(Expression::Concat(parts));
+        }
+        _ => Err(format!(
+            "Unknown function {} (use lower, substr, regex, year, month, day, hash or concat)",
+            name
+        ))?,
+    };
+
+    match args.next() {
+        None => Ok(expression),
+        Some(_) => Err(format!("Too many arguments for {}", name))?,
+    }
+}
+
+fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "''"))
+}
+
+/// Keep a file name readable, but free of path separators and other special characters
+fn escape(text: &str) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'_' | b'-' | b'.' | b',' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn lower(value: &[u8]) -> Vec<u8> {
+    match std::str::from_utf8(value) {
+        Ok(text) => text.to_lowercase().into_bytes(),
+        Err(_) => value.to_ascii_lowercase(),
+    }
+}
+
+fn substr(value: &[u8], from: usize, len: Option<usize>) -> Vec<u8> {
+    let text = String::from_utf8_lossy(value);
+    let chars = text.chars().skip(from - 1);
+    match len {
+        Some(len) => chars.take(len).collect::<String>().into_bytes(),
+        None => chars.collect::<String>().into_bytes(),
+    }
+}
+
+fn capture(regex: &Regex, value: &[u8]) -> Option<Vec<u8>> {
+    let captures = regex.captures(value)?;
+    captures
+        .get(1)
+        .or_else(|| captures.get(0))
+        .map(|m| m.as_bytes().to_vec())
+}
+
+/// Leading YYYY, YYYY-MM or YYYY-MM-DD of an ISO 8601 date
+fn truncate(value: &[u8], unit: Unit) -> Option<Vec<u8>> {
+    let len = match unit {
+        Unit::YEAR => 4,
+        Unit::MONTH => 7,
+        Unit::DAY => 10,
+    };
+    let prefix = value.get(..len)?;
+    let valid = prefix.iter().enumerate().all(|(i, b)| match i {
+        4 | 7 => *b == b'-',
+        _ => b.is_ascii_digit(),
+    });
+
+    if valid {
+        Some(prefix.to_vec())
+    } else {
+        None
+    }
+}
+
+/// 64 bit FNV-1a, a small hash that is stable between versions and platforms
+pub fn fnv1a(value: &[u8]) -> u64 {
+    value.iter().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ u64::from(*b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(expression: &str, fields: &[&str]) -> Option<String> {
+        let record = ByteRecord::from(fields.to_vec());
+        Expression::parse(expression)
+            .unwrap()
+            .eval(&record)
+            .map(|key| String::from_utf8(key).unwrap())
+    }
+
+    #[test]
+    fn test_parse() {
+        let expression = Expression::parse(" lower( regex(4, '@(.+)$') ) ").unwrap();
+        assert_eq!(expression.to_string(), "lower(regex(4,'@(.+)$'))");
+        assert_eq!(Expression::parse("3").unwrap().to_string(), "3");
+        assert_eq!(
+            Expression::parse("CONCAT(1,'it''s',2)")
+                .unwrap()
+                .to_string(),
+            "concat(1,'it''s',2)"
+        );
+
+        assert!(Expression::parse("0").is_err());
+        assert!(Expression::parse("upper(1)").is_err());
+        assert!(Expression::parse("lower(1").is_err());
+        assert!(Expression::parse("lower(1,2)").is_err());
+        assert!(Expression::parse("substr(1)").is_err());
+        assert!(Expression::parse("regex(1,'(')").is_err());
+        assert!(Expression::parse("1 2").is_err());
+    }
+
+    #[test]
+    fn test_eval() {
+        let fields = ["42", "Ann", "Ann@Example.COM", "2019-05-17T10:00:00Z"];
+        assert_eq!(eval("2", &fields), Some("Ann".to_owned()));
+        assert_eq!(eval("5", &fields), None);
+        assert_eq!(
+            eval("lower(regex(3,'@(.+)$'))", &fields),
+            Some("example.com".to_owned())
+        );
+        assert_eq!(eval("regex(3,'z')", &fields), None);
+        assert_eq!(eval("regex(1,'[0-9]')", &fields), Some("4".to_owned()));
+        assert_eq!(eval("substr(3,5,3)", &fields), Some("Exa".to_owned()));
+        assert_eq!(eval("substr(2,2)", &fields), Some("nn".to_owned()));
+        assert_eq!(eval("year(4)", &fields), Some("2019".to_owned()));
+        assert_eq!(eval("month(4)", &fields), Some("2019-05".to_owned()));
+        assert_eq!(eval("day(4)", &fields), Some("2019-05-17".to_owned()));
+        assert_eq!(eval("month(2)", &fields), None);
+        assert_eq!(eval("concat(2,'-',1)", &fields), Some("Ann-42".to_owned()));
+        assert_eq!(
+            eval("hash(2)", &fields),
+            eval("hash(concat('A','nn'))", &fields)
+        );
+        assert_eq!(
+            eval("hash('')", &fields),
+            Some("cbf29ce484222325".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_index_path() {
+        assert_eq!(
+            Expression::parse("3").unwrap().index_path("a.csv"),
+            "a.csv.index.3"
+        );
+        assert_eq!(
+            Expression::parse("month(3)").unwrap().index_path("a.csv"),
+            "a.csv.index.month%283%29"
+        );
+        assert_eq!(
+            Expression::parse("regex(1,'^a/b')")
+                .unwrap()
+                .index_path("a.csv"),
+            "a.csv.index.regex%281,%27%5Ea%2Fb%27%29"
+        );
+    }
+}
@@ -1,15 +1,36 @@
-use crate::toc::TypedToc;
+use crate::csv_index::parse_hex;
+use crate::datetime::TimeType;
+use crate::decimal::{self, DecimalType};
+use crate::expression::Expression;
+use crate::fuzzy::{self, Levenshtein};
+use crate::hash;
+use crate::ip;
+use crate::normalize::Normalization;
+use crate::number::NumberFormat;
+use crate::printer::Printer;
+use crate::range::Range;
+use crate::sparse;
+use crate::text;
+use crate::toc::{Toc, TypedToc};
+use crate::trigram::{self, Pattern};
 
+use log::debug;
+use regex::bytes::RegexBuilder;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use std::borrow::Cow;
+use std::collections::HashSet;
 use std::error::Error;
+use std::fmt::Debug;
 use std::fs::File;
 use std::io::Write;
+use std::str::FromStr;
 
 use std::ops::Bound::{self, Excluded, Included, Unbounded};
 
-use std::f64;
-use std::i64;
-
 use crate::unsafe_float::UnsafeFloat;
+use crate::uuid;
 
 pub enum Operator {
     EQ,
@@ -19,6 +40,15 @@ pub enum Operator {
     GE,
     IN,
     PRE,
+    NOTPRE,
+    SUF,
+    CONTAINS,
+    REGEX,
+    MATCH,
+    FUZZY,
+    ISNULL,
+    NOTNULL,
+    CIDR,
 }
 
 impl Operator {
@@ -31,20 +61,33 @@ impl Operator {
             "GT" => Ok(Operator::GT),
             "IN" => Ok(Operator::IN),
             "PRE" => Ok(Operator::PRE),
+            "NOT-PRE" => Ok(Operator::NOTPRE),
+            "SUF" => Ok(Operator::SUF),
+            "CONTAINS" => Ok(Operator::CONTAINS),
+            "REGEX" => Ok(Operator::REGEX),
+            "MATCH" => Ok(Operator::MATCH),
+            "FUZZY" => Ok(Operator::FUZZY),
+            "IS-NULL" => Ok(Operator::ISNULL),
+            "NOT-NULL" => Ok(Operator::NOTNULL),
+            "CIDR" => Ok(Operator::CIDR),
             _ => Err("Unknown operator"),
         }
     }
+
+    pub fn needs_value(&self) -> bool {
+        !matches!(self, Operator::ISNULL | Operator::NOTNULL)
+    }
 }
 
 pub struct Filter<'a> {
     op: Operator,
     value: &'a str,
     value2: &'a str,
-    column: usize,
+    column: &'a Expression,
 }
 
 impl<'a> Filter<'a> {
-    pub fn from(op: Operator, value: &'a str, value2: &'a str, column: usize) -> Self {
+    pub fn from(op: Operator, value: &'a str, value2: &'a str, column: &'a Expression) -> Self {
         Filter {
             op,
             value,
@@ -53,101 +96,756 @@ impl<'a> Filter<'a> {
         }
     }
 
-    fn string_bounds(&self) -> (Bound<Vec<u8>>, Bound<Vec<u8>>) {
+    /// Key ranges of a str index, sorted and disjoint (only `not-pre` needs more than one)
+    pub fn string_ranges(
+        &self,
+        normalization: &Normalization,
+    ) -> Result<Vec<Range<Vec<u8>>>, Box<dyn Error>> {
+        // keys of a reversed index are only ordered by their suffix
         match self.op {
-            Operator::EQ => (
-                Included(self.value.as_bytes().to_owned()),
-                Included(self.value.as_bytes().to_owned()),
-            ),
-            Operator::LE => (Unbounded, Included(self.value.as_bytes().to_owned())),
-            Operator::LT => (Unbounded, Excluded(self.value.as_bytes().to_owned())),
-            Operator::GT => (Excluded(self.value.as_bytes().to_owned()), Unbounded),
-            Operator::GE => (Included(self.value.as_bytes().to_owned()), Unbounded),
-            Operator::IN => (
-                Included(self.value.as_bytes().to_owned()),
-                Included(self.value2.as_bytes().to_owned()),
-            ),
-            Operator::PRE => {
-                let mut upper = self.value.as_bytes().to_owned();
-                upper.append(&mut vec![255; 4]);
-                (Included(self.value.as_bytes().to_owned()), Included(upper))
+            Operator::SUF if !normalization.is_reversed() => {
+                Err("suf needs an index built with --reverse")?
+            }
+            Operator::EQ | Operator::SUF | Operator::ISNULL | Operator::NOTNULL => (),
+            _ if normalization.is_reversed() => {
+                Err("only eq, suf, is-null and not-null can be used on a reversed index")?
+            }
+            _ => (),
+        }
+
+        let value = normalization.key(self.value.as_bytes()).into_owned();
+        let value2 = normalization.key(self.value2.as_bytes()).into_owned();
+
+        let bounds = match self.op {
+            Operator::EQ => (Included(value.clone()), Included(value)),
+            Operator::LE => (Unbounded, Included(value)),
+            Operator::LT => (Unbounded, Excluded(value)),
+            Operator::GT => (Excluded(value), Unbounded),
+            Operator::GE => (Included(value), Unbounded),
+            Operator::IN => (Included(value), Included(value2)),
+            Operator::PRE => prefix_range(normalization.prefix(self.value.as_bytes())),
+            Operator::SUF => prefix_range(normalization.suffix(self.value.as_bytes())),
+            Operator::NOTPRE => {
+                // empty values are nulls, so they never match, like with not-null
+                let prefix = normalization.prefix(self.value.as_bytes()).into_owned();
+                let mut ranges = vec![];
+                if !prefix.is_empty() {
+                    ranges.push((Excluded(vec![]), Excluded(prefix.clone())));
+                }
+                if let Some(successor) = prefix_successor(&prefix) {
+                    ranges.push((Included(successor), Unbounded));
+                }
+                return Ok(ranges);
+            }
+            // empty values are the nulls of a string index
+            Operator::ISNULL => (Included(vec![]), Included(vec![])),
+            Operator::NOTNULL => (Excluded(vec![]), Unbounded),
+            Operator::CONTAINS | Operator::REGEX => {
+                Err("contains and regex need an index of type trigram")?
             }
+            Operator::MATCH => Err("match needs an index of type text")?,
+            Operator::FUZZY => Err("fuzzy can only be used in filter")?,
+            Operator::CIDR => Err("unsupported operator for str")?,
+        };
+
+        Ok(vec![bounds])
+    }
+
+    pub fn int_bounds(
+        &self,
+        number_format: &NumberFormat,
+    ) -> Result<(Bound<i64>, Bound<i64>), Box<dyn Error>> {
+        self.ordered_bounds("int", |value| number_format.parse_int_value(value))
+    }
+
+    pub fn u64_bounds(&self) -> Result<(Bound<u64>, Bound<u64>), Box<dyn Error>> {
+        self.ordered_bounds("u64", |value| value.parse().ok())
+    }
+
+    pub fn i128_bounds(&self) -> Result<(Bound<i128>, Bound<i128>), Box<dyn Error>> {
+        self.ordered_bounds("i128", |value| value.parse().ok())
+    }
+
+    pub fn u128_bounds(&self) -> Result<(Bound<u128>, Bound<u128>), Box<dyn Error>> {
+        self.ordered_bounds("u128", |value| value.parse().ok())
+    }
+
+    pub fn hex_bounds(&self) -> Result<(Bound<u128>, Bound<u128>), Box<dyn Error>> {
+        self.ordered_bounds("hex", |value| parse_hex(value.as_bytes()))
+    }
+
+    pub fn ip_bounds(&self) -> Result<(Bound<u128>, Bound<u128>), Box<dyn Error>> {
+        if let Operator::CIDR = self.op {
+            let (first, last) = ip::cidr(self.value)
+                .ok_or_else(|| format!("Invalid cidr value: {:?}", self.value))?;
+            return Ok((Included(first), Included(last)));
         }
+
+        self.ordered_bounds("ip", ip::parse)
     }
 
-    fn int_bounds(&self) -> (Bound<i64>, Bound<i64>) {
-        let value: i64 = self.value.parse().unwrap_or(i64::MIN);
+    pub fn uuid_bounds(&self) -> Result<(Bound<[u8; 16]>, Bound<[u8; 16]>), Box<dyn Error>> {
+        self.ordered_bounds("uuid", |value| uuid::parse(value.as_bytes()))
+    }
 
+    pub fn time_bounds(
+        &self,
+        time_type: &TimeType,
+    ) -> Result<(Bound<i64>, Bound<i64>), Box<dyn Error>> {
+        self.ordered_bounds("date/time", |value| time_type.parse_value(value))
+    }
+
+    pub fn decimal_bounds(
+        &self,
+        decimal_type: &DecimalType,
+    ) -> Result<(Bound<i64>, Bound<i64>), Box<dyn Error>> {
         match self.op {
-            Operator::EQ => (Included(value), Included(value)),
-            Operator::LE => (Excluded(i64::MIN), Included(value)),
-            Operator::LT => (Excluded(i64::MIN), Excluded(value)),
-            Operator::GT => (Excluded(value), Excluded(i64::MAX)),
-            Operator::GE => (Included(value), Excluded(i64::MAX)),
+            Operator::NOTNULL => return Ok((Unbounded, Unbounded)),
+            Operator::ISNULL => Err("is-null can only be used in filter")?,
+            Operator::PRE
+            | Operator::NOTPRE
+            | Operator::SUF
+            | Operator::CONTAINS
+            | Operator::REGEX
+            | Operator::MATCH
+            | Operator::FUZZY
+            | Operator::CIDR => Err("unsupported operator for decimal")?,
+            _ => (),
+        }
+
+        let parse = |value: &str| {
+            decimal::parse(value.as_bytes())
+                .and_then(|parsed| decimal_type.floor_ceil(parsed))
+                .ok_or_else(|| format!("Invalid decimal value: {:?}", value))
+        };
+
+        // values with more fraction digits than the index are rounded towards the matching keys,
+        // so e.g. `eq 19.995` matches nothing on an index with scale 2
+        let (floor, ceil) = parse(self.value)?;
+        let exact = floor == ceil;
+
+        let bounds = match self.op {
+            Operator::EQ => (Included(ceil), Included(floor)),
+            Operator::LE => (Unbounded, Included(floor)),
+            Operator::LT if exact => (Unbounded, Excluded(floor)),
+            Operator::LT => (Unbounded, Included(floor)),
+            Operator::GT if exact => (Excluded(ceil), Unbounded),
+            Operator::GT => (Included(ceil), Unbounded),
+            Operator::GE => (Included(ceil), Unbounded),
             Operator::IN => {
-                let value2: i64 = self.value2.parse().unwrap_or(i64::MIN);
-                (Included(value), Included(value2))
+                let (floor2, _) = parse(self.value2)?;
+                (Included(ceil), Included(floor2))
             }
-            Operator::PRE => panic!("unsupported operator for integer"),
-        }
+            Operator::PRE
+            | Operator::NOTPRE
+            | Operator::SUF
+            | Operator::CONTAINS
+            | Operator::REGEX
+            | Operator::MATCH
+            | Operator::FUZZY
+            | Operator::ISNULL
+            | Operator::NOTNULL
+            | Operator::CIDR => unreachable!(),
+        };
+
+        Ok(bounds)
     }
 
-    fn float_bounds(&self) -> (Bound<UnsafeFloat>, Bound<UnsafeFloat>) {
-        let value = UnsafeFloat(self.value.parse().unwrap_or(f64::NEG_INFINITY));
+    fn ordered_bounds<T, F>(
+        &self,
+        type_name: &str,
+        parse: F,
+    ) -> Result<(Bound<T>, Bound<T>), Box<dyn Error>>
+    where
+        T: Clone,
+        F: Fn(&str) -> Option<T>,
+    {
+        match self.op {
+            Operator::NOTNULL => return Ok((Unbounded, Unbounded)),
+            Operator::ISNULL => Err("is-null can only be used in filter")?,
+            Operator::PRE
+            | Operator::NOTPRE
+            | Operator::SUF
+            | Operator::CONTAINS
+            | Operator::REGEX
+            | Operator::MATCH
+            | Operator::FUZZY
+            | Operator::CIDR => Err(format!("unsupported operator for {}", type_name))?,
+            _ => (),
+        }
+
+        let invalid = |value| format!("Invalid {} value: {:?}", type_name, value);
+        let value = parse(self.value).ok_or_else(|| invalid(self.value))?;
 
-        let lower = Excluded(UnsafeFloat(f64::NEG_INFINITY));
-        let upper = Excluded(UnsafeFloat(f64::INFINITY));
+        let bounds = match self.op {
+            Operator::EQ => (Included(value.clone()), Included(value)),
+            Operator::LE => (Unbounded, Included(value)),
+            Operator::LT => (Unbounded, Excluded(value)),
+            Operator::GT => (Excluded(value), Unbounded),
+            Operator::GE => (Included(value), Unbounded),
+            Operator::IN => {
+                let value2 = parse(self.value2).ok_or_else(|| invalid(self.value2))?;
+                (Included(value), Included(value2))
+            }
+            Operator::PRE
+            | Operator::NOTPRE
+            | Operator::SUF
+            | Operator::CONTAINS
+            | Operator::REGEX
+            | Operator::MATCH
+            | Operator::FUZZY
+            | Operator::ISNULL
+            | Operator::NOTNULL
+            | Operator::CIDR => unreachable!(),
+        };
 
+        Ok(bounds)
+    }
+
+    pub fn float_bounds(
+        &self,
+        number_format: &NumberFormat,
+    ) -> Result<(Bound<UnsafeFloat>, Bound<UnsafeFloat>), Box<dyn Error>> {
         match self.op {
+            // NaN is not a number to count or sum, see Agg::execute
+            Operator::NOTNULL => return Ok((Unbounded, Excluded(UnsafeFloat::NAN))),
+            Operator::ISNULL => Err("is-null can only be used in filter")?,
+            Operator::PRE
+            | Operator::NOTPRE
+            | Operator::SUF
+            | Operator::CONTAINS
+            | Operator::REGEX
+            | Operator::MATCH
+            | Operator::FUZZY
+            | Operator::CIDR => Err("unsupported operator for float")?,
+            _ => (),
+        }
+
+        let parse = |value: &str| {
+            number_format
+                .parse_float_value(value)
+                .map(UnsafeFloat)
+                .ok_or_else(|| format!("Invalid float value: {:?}", value))
+        };
+        let value = parse(self.value)?;
+
+        // NaN sorts above infinity, it is only matched by `eq nan` or `in x nan`
+        let upper = if value == UnsafeFloat::NAN {
+            Unbounded
+        } else {
+            Excluded(UnsafeFloat::NAN)
+        };
+
+        let bounds = match self.op {
             Operator::EQ => (Included(value), Included(value)),
-            Operator::LE => (lower, Included(value)),
-            Operator::LT => (lower, Excluded(value)),
+            Operator::LE => (Unbounded, Included(value)),
+            Operator::LT => (Unbounded, Excluded(value)),
             Operator::GT => (Excluded(value), upper),
             Operator::GE => (Included(value), upper),
             Operator::IN => {
-                let value2 = UnsafeFloat(self.value2.parse().unwrap_or(f64::NEG_INFINITY));
+                let value2 = parse(self.value2)?;
                 (Included(value), Included(value2))
             }
-            Operator::PRE => panic!("unsupported operator for float"),
-        }
+            Operator::PRE
+            | Operator::NOTPRE
+            | Operator::SUF
+            | Operator::CONTAINS
+            | Operator::REGEX
+            | Operator::MATCH
+            | Operator::FUZZY
+            | Operator::ISNULL
+            | Operator::NOTNULL
+            | Operator::CIDR => unreachable!(),
+        };
+
+        Ok(bounds)
     }
 
     pub fn execute<W: Write>(
         &self,
         file: &mut File,
         filename: &str,
-        mut writer: W,
+        writer: W,
+        select: Option<Vec<usize>>,
     ) -> Result<(), Box<Error>> {
-        let mut fh = File::open(format!("{}.index.{}", filename, self.column + 1))?;
+        let mut fh = File::open(self.column.index_path(filename))?;
         let typed_toc = TypedToc::open(&mut fh)?;
+        let mut printer = Printer::new(file, writer, select);
 
         match typed_toc {
-            TypedToc::STR(typed_toc) => {
-                let bounds = self.string_bounds();
-                let indexes = typed_toc.get_index(&mut fh, &bounds)?;
-                indexes.into_iter().for_each(|index| {
-                    let b_clone = (bounds.0.clone(), bounds.1.clone());
-                    index.print_matching_records(b_clone, &file, &mut writer);
-                });
-            }
-            TypedToc::I64(typed_toc) => {
-                let bounds = self.int_bounds();
-                let indexes = typed_toc.get_index(&mut fh, &bounds)?;
-                indexes.into_iter().for_each(|index| {
-                    let b_clone = (bounds.0, bounds.1);
-                    index.print_matching_records(b_clone, &file, &mut writer);
-                });
+            TypedToc::STR(toc, normalization) => match self.op {
+                Operator::FUZZY if toc.is_sparse() => {
+                    Err("fuzzy can not be used on a sparse index")?
+                }
+                Operator::FUZZY => self.print_fuzzy(toc, &normalization, &mut fh, &mut printer)?,
+                _ => self.print_ranges(
+                    toc,
+                    &mut fh,
+                    self.string_ranges(&normalization)?,
+                    |value| Some(normalization.key(value).into_owned()),
+                    &mut printer,
+                )?,
+            },
+            TypedToc::I64(toc, number_format) => self.print_typed(
+                toc,
+                &mut fh,
+                || self.int_bounds(&number_format),
+                |value| number_format.parse_int(value),
+                &mut printer,
+            )?,
+            TypedToc::F64(toc, number_format) => self.print_typed(
+                toc,
+                &mut fh,
+                || self.float_bounds(&number_format),
+                |value| number_format.parse_float(value).map(UnsafeFloat),
+                &mut printer,
+            )?,
+            TypedToc::TIME(toc, time_type) => self.print_typed(
+                toc,
+                &mut fh,
+                || self.time_bounds(&time_type),
+                |value| time_type.parse(value),
+                &mut printer,
+            )?,
+            TypedToc::DECIMAL(toc, decimal_type) => self.print_typed(
+                toc,
+                &mut fh,
+                || self.decimal_bounds(&decimal_type),
+                |value| decimal::parse(value).and_then(|parsed| decimal_type.key(parsed)),
+                &mut printer,
+            )?,
+            TypedToc::U64(toc) => self.print_typed(
+                toc,
+                &mut fh,
+                || self.u64_bounds(),
+                parse_number,
+                &mut printer,
+            )?,
+            TypedToc::I128(toc) => self.print_typed(
+                toc,
+                &mut fh,
+                || self.i128_bounds(),
+                parse_number,
+                &mut printer,
+            )?,
+            TypedToc::U128(toc) => self.print_typed(
+                toc,
+                &mut fh,
+                || self.u128_bounds(),
+                parse_number,
+                &mut printer,
+            )?,
+            TypedToc::HEX(toc) => {
+                self.print_typed(toc, &mut fh, || self.hex_bounds(), parse_hex, &mut printer)?
             }
-            TypedToc::F64(typed_toc) => {
-                let bounds = self.float_bounds();
-                let indexes = typed_toc.get_index(&mut fh, &bounds)?;
-                indexes.into_iter().for_each(|index| {
-                    let b_clone = (bounds.0, bounds.1);
-                    index.print_matching_records(b_clone, &file, &mut writer);
-                });
+            TypedToc::IP(toc) => self.print_typed(
+                toc,
+                &mut fh,
+                || self.ip_bounds(),
+                |value| std::str::from_utf8(value).ok().and_then(ip::parse),
+                &mut printer,
+            )?,
+            TypedToc::UUID(toc) => self.print_typed(
+                toc,
+                &mut fh,
+                || self.uuid_bounds(),
+                uuid::parse,
+                &mut printer,
+            )?,
+            TypedToc::TRIGRAM(toc, normalization) => {
+                let pattern = match self.op {
+                    Operator::ISNULL => None,
+                    Operator::NOTNULL => Some(Pattern::Contains(vec![])),
+                    Operator::CONTAINS => {
+                        let needle = normalization.fragment(self.value.as_bytes());
+                        Some(Pattern::Contains(needle.into_owned()))
+                    }
+                    Operator::REGEX => {
+                        // the values are matched with their case folded
+                        let regex = RegexBuilder::new(self.value)
+                            .case_insensitive(normalization.folds_case())
+                            .build()?;
+                        Some(Pattern::Regex(regex))
+                    }
+                    _ => Err(
+                        "only contains, regex, is-null and not-null can be used on a trigram index",
+                    )?,
+                };
+                match pattern {
+                    None => print_nulls(toc, &mut fh, &mut printer)?,
+                    Some(pattern) => trigram::print_matches(
+                        toc,
+                        &normalization,
+                        &mut fh,
+                        file,
+                        self.column,
+                        &pattern,
+                        &mut printer,
+                    )?,
+                }
             }
+            TypedToc::TEXT(toc, tokenizer) => match self.op {
+                Operator::ISNULL => print_nulls(toc, &mut fh, &mut printer)?,
+                Operator::MATCH => text::print_matches(
+                    toc,
+                    &tokenizer,
+                    &mut fh,
+                    file,
+                    self.column,
+                    self.value,
+                    &mut printer,
+                )?,
+                _ => Err("only match and is-null can be used on a text index")?,
+            },
+            TypedToc::HASH(toc, normalization) => match self.op {
+                Operator::ISNULL => print_nulls(toc, &mut fh, &mut printer)?,
+                Operator::EQ => {
+                    let value = normalization.key(self.value.as_bytes());
+                    hash::print_matches(
+                        toc,
+                        &normalization,
+                        &mut fh,
+                        self.column,
+                        &value,
+                        &mut printer,
+                    )?
+                }
+                _ => Err("only eq and is-null can be used on a hash index")?,
+            },
         };
 
+        printer.finish()
+    }
+
+    /// Print the records with a key within the edit distance (1 by default), closest first
+    fn print_fuzzy<W: Write>(
+        &self,
+        toc: Toc<Vec<u8>>,
+        normalization: &Normalization,
+        fh: &mut File,
+        printer: &mut Printer<W>,
+    ) -> Result<(), Box<dyn Error>> {
+        let max_distance = if self.value2.is_empty() {
+            1
+        } else {
+            self.value2
+                .parse()
+                .map_err(|_| "The edit distance of fuzzy should be a number")?
+        };
+        let levenshtein = Levenshtein::new(&normalization.key(self.value.as_bytes()), max_distance);
+
+        printer.cover(toc.included());
+
+        // read one chapter at a time, and only keep the records of its matching keys
+        let mut matches = vec![];
+        for address in toc.find(&[(Unbounded, Unbounded)]) {
+            let index = Toc::read_chapter(fh, &address)?;
+            for (distance, key, addresses) in fuzzy::search(&index, &levenshtein) {
+                let records: Vec<_> = addresses
+                    .iter()
+                    .map(|address| (address.clone(), index.included(address).cloned()))
+                    .collect();
+                matches.push((distance, key.clone(), records));
+            }
+        }
+        matches.sort_by(|a, b| (a.0, &a.1).cmp(&(b.0, &b.1)));
+
+        let mut printed = HashSet::new();
+        for (distance, key, records) in matches {
+            debug!(
+                "{:?} at distance {}",
+                String::from_utf8_lossy(&key),
+                distance
+            );
+            for (address, included) in records {
+                if printed.insert(address.offset) {
+                    printer.print(&address, included.as_ref())?;
+                }
+            }
+        }
+
         Ok(())
     }
+
+    /// Print the matches of a typed index, where is-null reads the separate null list
+    fn print_typed<R, B, P, W>(
+        &self,
+        toc: Toc<R>,
+        fh: &mut File,
+        bounds: B,
+        parse: P,
+        printer: &mut Printer<W>,
+    ) -> Result<(), Box<dyn Error>>
+    where
+        R: Ord + Serialize + DeserializeOwned + Clone + Debug,
+        B: FnOnce() -> Result<Range<R>, Box<dyn Error>>,
+        P: Fn(&[u8]) -> Option<R>,
+        W: Write,
+    {
+        if let Operator::ISNULL = self.op {
+            return print_nulls(toc, fh, printer);
+        }
+
+        self.print_ranges(toc, fh, vec![bounds()?], parse, printer)
+    }
+
+    /// Print the records with a key in the ranges, parsing the keys of a sparse index from the csv file
+    fn print_ranges<R, P, W>(
+        &self,
+        toc: Toc<R>,
+        fh: &mut File,
+        ranges: Vec<Range<R>>,
+        parse: P,
+        printer: &mut Printer<W>,
+    ) -> Result<(), Box<dyn Error>>
+    where
+        R: Ord + Serialize + DeserializeOwned + Clone + Debug,
+        P: Fn(&[u8]) -> Option<R>,
+        W: Write,
+    {
+        if toc.is_sparse() {
+            return sparse::print_matches(toc, fh, self.column, ranges, parse, printer);
+        }
+
+        print_matches(toc, fh, ranges, printer)
+    }
+}
+
+/// Read a value that parses with `FromStr`, like the keys of u64, i128 and u128 indexes
+fn parse_number<R: FromStr>(value: &[u8]) -> Option<R> {
+    std::str::from_utf8(value).ok()?.parse().ok()
+}
+
+fn print_matches<R, W>(
+    toc: Toc<R>,
+    fh: &mut File,
+    ranges: Vec<Range<R>>,
+    printer: &mut Printer<W>,
+) -> Result<(), Box<dyn Error>>
+where
+    R: Ord + Serialize + DeserializeOwned + Clone + Debug,
+    W: Write,
+{
+    let multi_valued = toc.is_multi_valued();
+    printer.cover(toc.included());
+    let indexes = toc.get_index_ranges(fh, &ranges)?;
+
+    if multi_valued {
+        // a record matching through several of its elements is printed once
+        let mut printed = HashSet::new();
+        for index in indexes {
+            for bounds in &ranges {
+                let b_clone = (bounds.0.clone(), bounds.1.clone());
+                for (_key, addresses) in index.range(b_clone) {
+                    for address in addresses {
+                        if printed.insert(address.offset) {
+                            printer.print(address, index.included(address))?;
+                        }
+                    }
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    for index in indexes {
+        for bounds in &ranges {
+            let b_clone = (bounds.0.clone(), bounds.1.clone());
+            index.print_matching_records(b_clone, printer)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// All keys starting with prefix
+fn prefix_range(prefix: Cow<[u8]>) -> Range<Vec<u8>> {
+    let upper = match prefix_successor(&prefix) {
+        Some(successor) => Excluded(successor),
+        None => Unbounded,
+    };
+
+    (Included(prefix.into_owned()), upper)
+}
+
+/// Smallest key above all keys starting with prefix, or None when that is unbounded
+pub fn prefix_successor(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut successor = prefix.to_vec();
+    while let Some(last) = successor.pop() {
+        if last < 0xFF {
+            successor.push(last + 1);
+            return Some(successor);
+        }
+    }
+
+    None
+}
+
+fn print_nulls<R, W>(
+    toc: Toc<R>,
+    fh: &mut File,
+    printer: &mut Printer<W>,
+) -> Result<(), Box<dyn Error>>
+where
+    R: Ord + Serialize + DeserializeOwned + Clone + Debug,
+    W: Write,
+{
+    printer.cover(toc.included());
+    let (nulls, included) = toc.get_nulls(fh)?;
+    for address in &nulls {
+        printer.print(address, included.get(&address.offset))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::IndexOptions;
+    use crate::test_files::CsvFile;
+
+    fn ranges(op: &str, value: &str) -> Vec<Range<Vec<u8>>> {
+        let filter = Filter::from(
+            Operator::from(op).unwrap(),
+            value,
+            "",
+            &Expression::Column(0),
+        );
+        filter.string_ranges(&Normalization::default()).unwrap()
+    }
+
+    #[test]
+    fn test_nulls() {
+        let mut csv = CsvFile::new("filter_nulls", b"id,amount\n1,10\n2,\n3,x\n4,5\n");
+        let amount = Expression::Column(1);
+        csv.index(&amount, "int", &IndexOptions::default()).unwrap();
+
+        let filter = |op, select| {
+            let filter = Filter::from(Operator::from(op).unwrap(), "", "", &amount);
+            let output = csv.filter(&filter, select).unwrap();
+            output.lines().map(str::to_owned).collect::<Vec<_>>()
+        };
+        assert_eq!(filter("not-null", None), vec!["4,5", "1,10"]);
+
+        // nulls are not sorted
+        let mut nulls = filter("is-null", None);
+        nulls.sort();
+        assert_eq!(nulls, vec!["2,", "3,x"]);
+
+        let mut ids = filter("is-null", Some(vec![0]));
+        ids.sort();
+        assert_eq!(ids, vec!["2", "3"]);
+    }
+
+    #[test]
+    fn test_covered_nulls() {
+        let mut csv = CsvFile::new("filter_covered_nulls", b"id,amount\n1,10\n2,\n4,5\n");
+        let amount = Expression::Column(1);
+        let options = IndexOptions {
+            include: vec![0],
+            ..Default::default()
+        };
+        csv.index(&amount, "int", &options).unwrap();
+
+        let filter = |op| Filter::from(Operator::from(op).unwrap(), "", "", &amount);
+        assert_eq!(
+            csv.filter(&filter("is-null"), Some(vec![0])).unwrap(),
+            "2\n"
+        );
+        assert_eq!(
+            csv.filter(&filter("not-null"), Some(vec![0])).unwrap(),
+            "4\n1\n"
+        );
+    }
+
+    #[test]
+    fn test_fuzzy() {
+        let mut csv = CsvFile::new(
+            "filter_fuzzy",
+            b"id,name\n1,jansen\n2,smith\n3,janssen\n4,jonson\n5,jansen\n6,smit\n7,\n",
+        );
+        let name = Expression::Column(1);
+        csv.index(&name, "str", &IndexOptions::default()).unwrap();
+
+        let filter = |value, value2| {
+            let filter = Filter::from(Operator::FUZZY, value, value2, &name);
+            csv.filter(&filter, Some(vec![0])).unwrap()
+        };
+        // closest first, the keys are spread over several chapters
+        assert_eq!(filter("jansen", ""), "5\n1\n3\n");
+        assert_eq!(filter("jonsen", "1"), "5\n1\n4\n");
+        assert_eq!(filter("smyth", "1"), "2\n");
+        assert_eq!(filter("smyth", "2"), "2\n6\n");
+    }
+
+    #[test]
+    fn test_null_bounds() {
+        let filter = |op| Filter::from(op, "", "", &Expression::Column(0));
+        let number_format = NumberFormat::default();
+
+        assert_eq!(
+            filter(Operator::NOTNULL)
+                .int_bounds(&number_format)
+                .unwrap(),
+            (Unbounded, Unbounded)
+        );
+        // is-null reads the null list instead of a range of keys
+        assert!(filter(Operator::ISNULL).int_bounds(&number_format).is_err());
+        assert!(filter(Operator::ISNULL).u64_bounds().is_err());
+
+        // empty values are the nulls of a string index
+        assert_eq!(
+            ranges("is-null", ""),
+            vec![(Included(vec![]), Included(vec![]))]
+        );
+        assert_eq!(ranges("not-null", ""), vec![(Excluded(vec![]), Unbounded)]);
+    }
+
+    #[test]
+    fn test_suf() {
+        let reversed = Normalization::try_new(false, false, None, false, true).unwrap();
+        let filter = Filter::from(Operator::SUF, ".gov", "", &Expression::Column(0));
+        assert_eq!(
+            filter.string_ranges(&reversed).unwrap(),
+            vec![(Included(b"vog.".to_vec()), Excluded(b"vog/".to_vec()))]
+        );
+        assert!(filter.string_ranges(&Normalization::default()).is_err());
+
+        let filter = Filter::from(Operator::LT, "a", "", &Expression::Column(0));
+        assert!(filter.string_ranges(&reversed).is_err());
+    }
+
+    #[test]
+    fn test_prefix_successor() {
+        assert_eq!(prefix_successor(b"abc"), Some(b"abd".to_vec()));
+        assert_eq!(prefix_successor(b"ab\xff"), Some(b"ac".to_vec()));
+        assert_eq!(
+            prefix_successor(b"a\xff\xff\xff\xff\xff"),
+            Some(b"b".to_vec())
+        );
+        assert_eq!(prefix_successor(b"\xff\xff"), None);
+        assert_eq!(prefix_successor(b""), None);
+    }
+
+    #[test]
+    fn test_pre() {
+        assert_eq!(
+            ranges("pre", "ab"),
+            vec![(Included(b"ab".to_vec()), Excluded(b"ac".to_vec()))]
+        );
+        assert_eq!(ranges("pre", ""), vec![(Included(vec![]), Unbounded)]);
+    }
+
+    #[test]
+    fn test_not_pre() {
+        assert_eq!(
+            ranges("not-pre", "ab"),
+            vec![
+                (Excluded(vec![]), Excluded(b"ab".to_vec())),
+                (Included(b"ac".to_vec()), Unbounded)
+            ]
+        );
+        assert!(ranges("not-pre", "").is_empty());
+    }
 }
@@ -0,0 +1,189 @@
+use crate::address::Address;
+use crate::csv_index::CsvIndex;
+use crate::filter::prefix_successor;
+
+use std::ops::Bound::{Excluded, Included, Unbounded};
+
+/// Levenshtein automaton: accepts the strings within an edit distance of the query
+pub struct Levenshtein {
+    query: Vec<char>,
+    max_distance: usize,
+}
+
+impl Levenshtein {
+    pub fn new(query: &[u8], max_distance: usize) -> Self {
+        Levenshtein {
+            query: String::from_utf8_lossy(query).chars().collect(),
+            max_distance,
+        }
+    }
+
+    /// State before reading any character: the distances to every prefix of the query
+    fn start(&self) -> Vec<usize> {
+        (0..=self.query.len()).collect()
+    }
+
+    fn step(&self, state: &[usize], c: char) -> Vec<usize> {
+        let mut next = Vec::with_capacity(state.len());
+        next.push(state[0] + 1);
+        for (i, q) in self.query.iter().enumerate() {
+            let substitution = state[i] + if *q == c { 0 } else { 1 };
+            next.push(substitution.min(state[i + 1] + 1).min(next[i] + 1));
+        }
+        next
+    }
+
+    /// Whether any continuation of the input read so far can still be accepted
+    fn can_match(&self, state: &[usize]) -> bool {
+        state.iter().any(|distance| *distance <= self.max_distance)
+    }
+
+    fn distance(&self, state: &[usize]) -> Option<usize> {
+        state.last().cloned().filter(|d| *d <= self.max_distance)
+    }
+}
+
+/// Keys within the edit distance of the query, with their distance, in key order
+///
+/// Walks the sorted keys sharing the automaton states of common prefixes, and skips all keys
+/// starting with a prefix that can no longer match.
+pub fn search<'a>(
+    index: &'a CsvIndex<Vec<u8>>,
+    levenshtein: &Levenshtein,
+) -> Vec<(usize, &'a Vec<u8>, &'a Vec<Address>)> {
+    let mut matches = vec![];
+
+    // states[i] is the state after reading the first i chars of prefix
+    let mut states = vec![levenshtein.start()];
+    let mut prefix: Vec<char> = vec![];
+
+    // empty keys are the nulls of a str index
+    let mut from = Excluded(vec![]);
+    'walk: loop {
+        for (key, addresses) in index.range((from.clone(), Unbounded)) {
+            let text = String::from_utf8_lossy(key);
+            let chars: Vec<char> = text.chars().collect();
+
+            let common = prefix
+                .iter()
+                .zip(&chars)
+                .take_while(|(a, b)| a == b)
+                .count();
+            states.truncate(common + 1);
+
+            let mut dead = false;
+            for c in &chars[common..] {
+                let state = levenshtein.step(states.last().unwrap(), *c);
+                if !levenshtein.can_match(&state) {
+                    dead = true;
+                    break;
+                }
+                states.push(state);
+            }
+            prefix = chars;
+            prefix.truncate(states.len() - 1);
+
+            if !dead {
+                if let Some(distance) = levenshtein.distance(states.last().unwrap()) {
+                    matches.push((distance, key, addresses));
+                }
+                continue;
+            }
+
+            // continue after all keys starting with the dead prefix, unless that is not valid UTF-8
+            let dead_prefix: String = text.chars().take(states.len()).collect();
+            if key.starts_with(dead_prefix.as_bytes()) {
+                match prefix_successor(dead_prefix.as_bytes()) {
+                    Some(successor) => {
+                        from = Included(successor);
+                        continue 'walk;
+                    }
+                    None => break 'walk,
+                }
+            }
+        }
+
+        break;
+    }
+
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(keys: &[&str]) -> CsvIndex<Vec<u8>> {
+        let mut index = CsvIndex::new();
+        for (i, key) in keys.iter().enumerate() {
+            let address = Address {
+                offset: i as u64,
+                length: 1,
+            };
+            index
+                .entry(key.as_bytes().to_vec())
+                .or_default()
+                .push(address);
+        }
+        index
+    }
+
+    fn found<'a>(
+        index: &'a CsvIndex<Vec<u8>>,
+        query: &str,
+        max_distance: usize,
+    ) -> Vec<(usize, &'a str)> {
+        search(index, &Levenshtein::new(query.as_bytes(), max_distance))
+            .into_iter()
+            .map(|(distance, key, _)| (distance, std::str::from_utf8(key).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_search() {
+        let index = index(&[
+            "jansen", "janssen", "jansens", "johnson", "jonson", "smith", "smit", "smyth",
+        ]);
+        assert_eq!(
+            found(&index, "jansen", 1),
+            vec![(0, "jansen"), (1, "jansens"), (1, "janssen")]
+        );
+        assert_eq!(
+            found(&index, "smith", 1),
+            vec![(1, "smit"), (0, "smith"), (1, "smyth")]
+        );
+        assert_eq!(found(&index, "jonsen", 0), vec![]);
+        assert_eq!(
+            found(&index, "jonsen", 2),
+            vec![
+                (1, "jansen"),
+                (2, "jansens"),
+                (2, "janssen"),
+                (2, "johnson"),
+                (1, "jonson")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_nulls() {
+        let index = index(&["", "a", "ab"]);
+        assert_eq!(found(&index, "b", 1), vec![(1, "a"), (1, "ab")]);
+        assert_eq!(found(&index, "", 2), vec![(1, "a"), (2, "ab")]);
+    }
+
+    #[test]
+    fn test_unicode() {
+        let index = index(&["café", "cafe", "cafés"]);
+        assert_eq!(found(&index, "cafe", 1), vec![(0, "cafe"), (1, "café")]);
+    }
+
+    #[test]
+    fn test_distance() {
+        let levenshtein = Levenshtein::new(b"kitten", 3);
+        let state = "sitting"
+            .chars()
+            .fold(levenshtein.start(), |state, c| levenshtein.step(&state, c));
+        assert_eq!(levenshtein.distance(&state), Some(3));
+    }
+}
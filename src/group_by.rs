@@ -0,0 +1,280 @@
+use crate::csv_index::{format_hex, FormatKey};
+use crate::expression::Expression;
+use crate::ip;
+use crate::toc::{Toc, TypedToc};
+use crate::uuid;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::Write;
+
+use std::ops::Bound::Unbounded;
+
+/// Formatted key with the sorted offsets of all records holding it
+type Postings = Vec<(Vec<u8>, Vec<u64>)>;
+
+pub enum Format {
+    CSV,
+    JSON,
+}
+
+impl Format {
+    pub fn from(format: &str) -> Result<Self, &'static str> {
+        match format.to_uppercase().as_ref() {
+            "CSV" => Ok(Format::CSV),
+            "JSON" => Ok(Format::JSON),
+            _ => Err("Unknown format"),
+        }
+    }
+}
+
+pub struct GroupBy {
+    column: Expression,
+    column2: Expression,
+    format: Format,
+}
+
+impl GroupBy {
+    pub fn from(column: Expression, column2: Expression, format: Format) -> Self {
+        GroupBy {
+            column,
+            column2,
+            format,
+        }
+    }
+
+    pub fn execute<W: Write>(&self, filename: &str, writer: W) -> Result<(), Box<dyn Error>> {
+        let postings = load_postings(filename, &self.column)?;
+        let postings2 = load_postings(filename, &self.column2)?;
+
+        let groups = count_groups(&postings, &postings2);
+
+        match self.format {
+            Format::CSV => write_csv(&postings, &postings2, &groups, writer),
+            Format::JSON => write_json(&postings, &postings2, &groups, writer),
+        }
+    }
+}
+
+fn load_postings(filename: &str, column: &Expression) -> Result<Postings, Box<dyn Error>> {
+    let mut fh = File::open(column.index_path(filename))?;
+    let typed_toc = TypedToc::open(&mut fh)?;
+    if typed_toc.is_sparse() {
+        Err("A sparse index does not hold all values of a column")?
+    }
+
+    match typed_toc {
+        TypedToc::STR(toc, normalization) => {
+            collect_postings(toc, &mut fh, |key| normalization.format_key(key))
+        }
+        TypedToc::I64(toc, _) => collect_postings(toc, &mut fh, FormatKey::format_key),
+        TypedToc::F64(toc, _) => collect_postings(toc, &mut fh, FormatKey::format_key),
+        TypedToc::TIME(toc, time_type) => {
+            collect_postings(toc, &mut fh, |key| time_type.format_key(*key))
+        }
+        TypedToc::DECIMAL(toc, decimal_type) => {
+            collect_postings(toc, &mut fh, |key| decimal_type.format_key(*key))
+        }
+        TypedToc::U64(toc) => collect_postings(toc, &mut fh, FormatKey::format_key),
+        TypedToc::I128(toc) => collect_postings(toc, &mut fh, FormatKey::format_key),
+        TypedToc::U128(toc) => collect_postings(toc, &mut fh, FormatKey::format_key),
+        TypedToc::HEX(toc) => collect_postings(toc, &mut fh, format_hex),
+        TypedToc::IP(toc) => collect_postings(toc, &mut fh, |key| ip::format(*key).into_bytes()),
+        TypedToc::UUID(toc) => collect_postings(toc, &mut fh, |key| uuid::format(key).into_bytes()),
+        TypedToc::TRIGRAM(..) => Err("A trigram index does not hold the values of a column")?,
+        TypedToc::TEXT(..) => Err("A text index does not hold the values of a column")?,
+        TypedToc::HASH(..) => Err("A hash index does not hold the values of a column")?,
+    }
+}
+
+fn collect_postings<R, F>(
+    toc: Toc<R>,
+    fh: &mut File,
+    format_key: F,
+) -> Result<Postings, Box<dyn Error>>
+where
+    R: Ord + Serialize + DeserializeOwned + Clone + Debug,
+    F: Fn(&R) -> Vec<u8>,
+{
+    let indexes = toc.get_index(fh, &(Unbounded, Unbounded))?;
+
+    let postings = indexes
+        .into_iter()
+        .flat_map(|index| index.into_map().into_iter())
+        .map(|(key, addresses)| {
+            let mut offsets: Vec<u64> = addresses.iter().map(|a| a.offset).collect();
+            offsets.sort_unstable();
+            (format_key(&key), offsets)
+        })
+        .collect();
+
+    Ok(postings)
+}
+
+/// Count the records for every (key, key2) pair, by intersecting the offset lists of both columns
+fn count_groups(postings: &Postings, postings2: &Postings) -> Vec<BTreeMap<usize, u64>> {
+    // all offsets of the second column, sorted, pointing to the key they belong to
+    let mut lookup: Vec<(u64, usize)> = postings2
+        .iter()
+        .enumerate()
+        .flat_map(|(i, (_key, offsets))| offsets.iter().map(move |&offset| (offset, i)))
+        .collect();
+    lookup.sort_unstable();
+
+    postings
+        .iter()
+        .map(|(_key, offsets)| {
+            let mut groups = BTreeMap::new();
+
+            // both lists are sorted, so every search can start where the previous one ended
+            let mut rest = &lookup[..];
+            for offset in offsets {
+                // a record of a multi-valued index is stored under several keys
+                let start = rest.partition_point(|&(o, _)| o < *offset);
+                let end = start
+                    + rest[start..]
+                        .iter()
+                        .take_while(|&&(o, _)| o == *offset)
+                        .count();
+                for &(_, i) in &rest[start..end] {
+                    *groups.entry(i).or_insert(0) += 1;
+                }
+                rest = &rest[end..];
+            }
+
+            groups
+        })
+        .collect()
+}
+
+fn write_csv<W: Write>(
+    postings: &Postings,
+    postings2: &Postings,
+    groups: &[BTreeMap<usize, u64>],
+    writer: W,
+) -> Result<(), Box<dyn Error>> {
+    let mut wtr = csv::Writer::from_writer(writer);
+    wtr.write_record(["value", "value2", "count"])?;
+
+    for (i, group) in groups.iter().enumerate() {
+        for (j, count) in group {
+            wtr.write_record([
+                &postings[i].0,
+                &postings2[*j].0,
+                count.to_string().as_bytes(),
+            ])?;
+        }
+    }
+
+    wtr.flush()?;
+    Ok(())
+}
+
+fn write_json<W: Write>(
+    postings: &Postings,
+    postings2: &Postings,
+    groups: &[BTreeMap<usize, u64>],
+    mut writer: W,
+) -> Result<(), Box<dyn Error>> {
+    write!(writer, "{{")?;
+
+    let nonempty = groups.iter().enumerate().filter(|(_, g)| !g.is_empty());
+    for (n, (i, group)) in nonempty.enumerate() {
+        if n > 0 {
+            write!(writer, ",")?;
+        }
+        write!(writer, "\n  {}: {{", json_string(&postings[i].0))?;
+
+        for (m, (j, count)) in group.iter().enumerate() {
+            if m > 0 {
+                write!(writer, ", ")?;
+            }
+            write!(writer, "{}: {}", json_string(&postings2[*j].0), count)?;
+        }
+
+        write!(writer, "}}")?;
+    }
+
+    writeln!(writer, "\n}}")?;
+    Ok(())
+}
+
+fn json_string(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() + 2);
+    out.push('"');
+    for c in String::from_utf8_lossy(bytes).chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::IndexOptions;
+    use crate::test_files::CsvFile;
+
+    #[test]
+    fn test_count_groups() {
+        let postings = vec![
+            (b"NL".to_vec(), vec![10, 30, 50]),
+            (b"US".to_vec(), vec![20, 40]),
+        ];
+        let postings2 = vec![
+            (b"closed".to_vec(), vec![30, 50]),
+            (b"open".to_vec(), vec![10, 20, 40]),
+        ];
+
+        let groups = count_groups(&postings, &postings2);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].get(&0), Some(&2));
+        assert_eq!(groups[0].get(&1), Some(&1));
+        assert_eq!(groups[1].get(&0), None);
+        assert_eq!(groups[1].get(&1), Some(&2));
+    }
+
+    #[test]
+    fn test_multi_valued() {
+        let mut csv = CsvFile::new(
+            "group_by_split",
+            b"id,country,tags\n1,NL,a;b\n2,US,b\n3,NL,a;b;c\n",
+        );
+        let country = Expression::Column(1);
+        let tags = Expression::Column(2);
+        csv.index(&country, "str", &IndexOptions::default())
+            .unwrap();
+        let split_by = IndexOptions {
+            split_by: Some(";".to_owned()),
+            ..Default::default()
+        };
+        csv.index(&tags, "str", &split_by).unwrap();
+
+        let mut output = vec![];
+        GroupBy::from(country, tags, Format::CSV)
+            .execute(csv.path(), &mut output)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "value,value2,count\nNL,a,2\nNL,b,2\nNL,c,1\nUS,b,1\n"
+        );
+    }
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(json_string(b"a\"b\\c\n"), "\"a\\\"b\\\\c\\n\"");
+    }
+}
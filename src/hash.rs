@@ -0,0 +1,83 @@
+use crate::expression::{fnv1a, Expression};
+use crate::normalize::Normalization;
+use crate::printer::Printer;
+use crate::toc::Toc;
+
+use log::debug;
+use std::error::Error;
+use std::fs::File;
+use std::io::Write;
+
+use std::ops::Bound::Included;
+
+/// Key of a value in a hash index, the value itself is not stored
+pub fn hash(value: &[u8]) -> u64 {
+    fnv1a(value)
+}
+
+/// Print the records with a value equal to the (normalized) value, from the one chapter of its hash
+///
+/// Every candidate is checked against the csv file, so values with the same hash never match.
+pub fn print_matches<W: Write>(
+    toc: Toc<u64>,
+    normalization: &Normalization,
+    fh: &mut File,
+    key: &Expression,
+    value: &[u8],
+    printer: &mut Printer<W>,
+) -> Result<(), Box<dyn Error>> {
+    let file = printer.file();
+    let hash = hash(value);
+
+    let indexes = toc.get_index(fh, &(Included(hash), Included(hash)))?;
+    let candidates: Vec<_> = indexes
+        .iter()
+        .flat_map(|index| index.range((Included(hash), Included(hash))))
+        .flat_map(|(_hash, addresses)| addresses.iter())
+        .collect();
+    debug!("{} candidate records", candidates.len());
+
+    for address in candidates {
+        let record = address.read_record(file)?;
+        let matches = key
+            .eval_raw(&record)
+            .is_some_and(|found| normalization.key(&found) == value);
+        if matches {
+            printer.print_raw(&record)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::{Filter, Operator};
+    use crate::index::IndexOptions;
+    use crate::test_files::CsvFile;
+
+    #[test]
+    fn test_print_matches() {
+        let input = b"id,email\n1,Ann@example.com\n2,bob@example.com\n3,ann@example.com\n4,\n";
+        let mut csv = CsvFile::new("hash", input);
+        let email = Expression::Column(1);
+        let options = IndexOptions {
+            fold_case: true,
+            ..Default::default()
+        };
+        csv.index(&email, "hash", &options).unwrap();
+
+        let filter = |op, value| {
+            let filter = Filter::from(Operator::from(op).unwrap(), value, "", &email);
+            let output = csv.filter(&filter, Some(vec![0])).unwrap();
+            let mut ids: Vec<_> = output.lines().map(str::to_owned).collect();
+            ids.sort();
+            ids
+        };
+        assert_eq!(filter("eq", "ann@example.com"), vec!["1", "3"]);
+        assert_eq!(filter("eq", "ANN@EXAMPLE.COM"), vec!["1", "3"]);
+        assert!(filter("eq", "carl@example.com").is_empty());
+        assert_eq!(filter("is-null", ""), vec!["4"]);
+    }
+}
@@ -1,20 +1,89 @@
+use crate::address::Address;
 use crate::csv_index::CsvIndexType;
 use crate::csv_reader::CsvReader;
+use crate::expression::Expression;
+use crate::predicate::Predicate;
 
 use log::{debug, info, trace};
 use std::time::Instant;
 
 use std::error::Error;
 use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
 
 use std::sync::{Arc, Mutex};
 use std::thread;
 
+/// Settings for building an index, besides the column and its type
+#[derive(Default, Clone)]
+pub struct IndexOptions {
+    /// Fail on values that do not parse as the index type (empty values are still allowed)
+    pub strict: bool,
+
+    /// In strict mode, write offending records to this file instead of failing
+    pub errors_to: Option<String>,
+
+    /// Input format of date, datetime and timestamp columns (see TimeFormat)
+    pub time_format: Option<String>,
+
+    /// Timezone of date and time values without an explicit offset
+    pub tz: Option<String>,
+
+    /// Fraction digits of a decimal index, detected from the data when not set
+    pub scale: Option<u32>,
+
+    /// Separator between groups of thousands in int and float values, e.g. `,` for 1,234
+    pub thousands_sep: Option<String>,
+
+    /// Separator before the fraction digits of float values, `.` when not set
+    pub decimal_sep: Option<String>,
+
+    /// Strip currency symbols around int and float values
+    pub currency: bool,
+
+    /// Read int and float values in parentheses as negative
+    pub accounting: bool,
+
+    /// Lowercase the keys of a str index
+    pub fold_case: bool,
+
+    /// Remove leading and trailing whitespace from the keys of a str index
+    pub trim: bool,
+
+    /// Unicode normalization form (nfc or nfkc) of the keys of a str index
+    pub normalize: Option<String>,
+
+    /// Remove accents from the keys of a str index, e.g. é becomes e
+    pub strip_accents: bool,
+
+    /// Store the keys of a str index reversed, for suffix queries
+    pub reverse: bool,
+
+    /// Strip common English word endings from the tokens of a text index
+    pub stem: bool,
+
+    /// Leave common English words out of a text index
+    pub stopwords: bool,
+
+    /// Separator of the elements of a multi-valued column, e.g. `;` for a;b;c
+    pub split_by: Option<String>,
+
+    /// Only index the records matching all these conditions, e.g. `col5 eq active`
+    pub conditions: Vec<String>,
+
+    /// Columns (starting at 0) of which the values are stored with every record
+    pub include: Vec<usize>,
+
+    /// Build a sparse index with blocks of this many records, for a file sorted by the key
+    pub sorted: Option<usize>,
+}
+
 pub fn index(
     filename: &str,
-    column: usize,
+    key: &Expression,
     csv_type: &str,
     threads: u64,
+    options: &IndexOptions,
 ) -> Result<(CsvIndexType, u64), Box<dyn Error>> {
     let file_size = std::fs::metadata(filename)?.len();
     debug!("file size {}", file_size);
@@ -22,15 +91,56 @@ pub fn index(
 
     let start = Instant::now();
 
-    let csv_index = Arc::new(Mutex::new(CsvIndexType::try_new(csv_type)?));
+    let mut csv_index = CsvIndexType::try_new(csv_type, options)?;
+    if options.errors_to.is_some() {
+        csv_index.keep_all_samples();
+    }
+    if let Some(separator) = &options.split_by {
+        if separator.is_empty() {
+            Err("The separator of --split-by can not be empty")?
+        }
+        csv_index.set_multi_valued()?;
+    }
+    if !options.include.is_empty() {
+        csv_index.include_columns(options.include.clone())?;
+    }
+    if let Some(block_size) = options.sorted {
+        check_sparse(options)?;
+        csv_index.set_sparse(block_size)?;
+    }
+    let csv_index = Arc::new(Mutex::new(csv_index));
+
+    let selection = Arc::new(Selection {
+        key: key.clone(),
+        predicates: options
+            .conditions
+            .iter()
+            .map(|condition| Predicate::parse(condition))
+            .collect::<Result<Vec<_>, _>>()?,
+        split_by: options.split_by.as_ref().map(|sep| sep.as_bytes().to_vec()),
+        include: options.include.clone(),
+    });
+
+    // abort as soon as one of the threads finds a malformed value
+    let fail_fast = options.strict && options.errors_to.is_none();
 
     let mut handles = Vec::new();
     for i in 0..threads {
         let thread_index = Arc::clone(&csv_index);
         let thread_file = File::open(filename)?;
+        let thread_selection = Arc::clone(&selection);
         let handle = thread::Builder::new()
             .name(format!("reader_{}", i))
-            .spawn(move || index_chunk(&thread_file, column, &thread_index, i, chunk_size))?;
+            .spawn(move || {
+                index_chunk(
+                    &thread_file,
+                    &thread_selection,
+                    &thread_index,
+                    i,
+                    chunk_size,
+                    fail_fast,
+                )
+            })?;
 
         handles.push(handle);
     }
@@ -39,7 +149,7 @@ pub fn index(
         .into_iter()
         .map(|handle| handle.join().unwrap_or_else(|_| panic!("Thread problem")))
         .collect::<Result<Vec<u64>, Box<dyn Error + Send>>>()
-        .unwrap()
+        .map_err(|e| e as Box<dyn Error>)?
         .iter()
         .sum::<u64>();
 
@@ -58,19 +168,106 @@ pub fn index(
     }
 
     index.print_range();
+    index.print_nulls();
+
+    if let (Some(errors_to), Some(nulls)) = (&options.errors_to, index.nulls()) {
+        write_rejects(filename, errors_to, &nulls.samples)?;
+        info!(
+            "Wrote {} rejected records to {}",
+            nulls.samples.len(),
+            errors_to
+        );
+    }
 
     Ok((index, counter))
 }
 
+/// Options that a sparse index can not be built with
+///
+/// A sparse query scans whole blocks of the csv file, so it would return the records that
+/// --where left out of the index.
+fn check_sparse(options: &IndexOptions) -> Result<(), &'static str> {
+    if options.sorted == Some(0) {
+        return Err("The block size of a sparse index should be at least 1");
+    }
+    if options.split_by.is_some() || !options.include.is_empty() || !options.conditions.is_empty() {
+        return Err("A sparse index can not be combined with --split-by, --include or --where");
+    }
+
+    Ok(())
+}
+
+/// Write offset, line number and raw value of the records with a malformed value
+fn write_rejects(
+    filename: &str,
+    errors_to: &str,
+    rejects: &[(Address, Vec<u8>)],
+) -> Result<(), Box<dyn Error>> {
+    let mut rejects: Vec<_> = rejects.iter().collect();
+    rejects.sort_unstable_by_key(|(address, _)| address.offset);
+
+    let offsets: Vec<u64> = rejects.iter().map(|(address, _)| address.offset).collect();
+    let lines = line_numbers(File::open(filename)?, &offsets)?;
+
+    let mut wtr = csv::Writer::from_path(errors_to)?;
+    wtr.write_record(["offset", "line", "value"])?;
+    for ((address, raw), line) in rejects.iter().zip(lines) {
+        wtr.write_record([
+            address.offset.to_string().as_bytes(),
+            line.to_string().as_bytes(),
+            raw,
+        ])?;
+    }
+    wtr.flush()?;
+
+    Ok(())
+}
+
+/// Line numbers (starting at 1) of the given sorted byte offsets, by counting newlines
+fn line_numbers<R: Read>(input: R, offsets: &[u64]) -> Result<Vec<u64>, Box<dyn Error>> {
+    let mut reader = BufReader::with_capacity(1 << 16, input);
+    let mut lines = Vec::with_capacity(offsets.len());
+
+    let mut pos = 0;
+    let mut line = 1;
+    for &offset in offsets {
+        while pos < offset {
+            let buf = reader.fill_buf()?;
+            if buf.is_empty() {
+                break;
+            }
+
+            let take = buf.len().min((offset - pos) as usize);
+            line += buf[..take].iter().filter(|&&b| b == b'\n').count() as u64;
+            reader.consume(take);
+            pos += take as u64;
+        }
+        lines.push(line);
+    }
+
+    Ok(lines)
+}
+
+/// Which records are indexed, and under which keys
+struct Selection {
+    key: Expression,
+    predicates: Vec<Predicate>,
+    split_by: Option<Vec<u8>>,
+    include: Vec<usize>,
+}
+
 fn index_chunk(
     file: &File,
-    column: usize,
+    selection: &Selection,
     index: &Arc<Mutex<CsvIndexType>>,
     pid: u64,
     chunk_size: u64,
+    fail_fast: bool,
 ) -> Result<u64, Box<dyn Error + Send>> {
     let offset = pid * chunk_size;
-    let reader = CsvReader::new(file, column, offset, chunk_size);
+    let mut reader = CsvReader::new(file, selection.key.clone(), offset, chunk_size)
+        .map_err(|e| Box::new(e) as Box<dyn Error + Send>)?;
+    let split_by = selection.split_by.as_ref().map(|sep| &sep[..]);
 
     if offset > 0 {
         debug!(
@@ -83,28 +280,233 @@ fn index_chunk(
     let mut counter = 0;
     let mut temp_results = Vec::with_capacity(100_000.min((chunk_size / 1000) as usize));
 
-    reader.for_each(|(address, value)| {
+    while let Some((address, value)) = reader.next() {
         trace!("THREAD{} read: {:?}", pid, value);
 
-        temp_results.push((value, address));
+        // a partial index leaves out the records not matching --where
+        if !selection
+            .predicates
+            .iter()
+            .all(|predicate| predicate.matches(reader.record()))
+        {
+            continue;
+        }
+
+        let included = if selection.include.is_empty() {
+            None
+        } else {
+            let record = reader.record();
+            let values = selection
+                .include
+                .iter()
+                .map(|column| record.get(*column).unwrap_or(b"").to_vec())
+                .collect();
+            Some(values)
+        };
+
+        temp_results.push((value, address, included));
 
         if temp_results.len() % 100_000 == 0 {
             counter += temp_results.len() as u64;
             debug!("THREAD{}: Processed {} items", pid, counter);
 
             let mut locked_index = index.lock().unwrap();
-            while let Some((value, address)) = temp_results.pop() {
-                locked_index.insert(value, address);
+            while let Some((value, address, included)) = temp_results.pop() {
+                insert(&mut locked_index, value, address, included, split_by);
+            }
+
+            // checked below, with the remaining results
+            if fail_fast && malformed(&locked_index).is_some() {
+                break;
             }
         }
-    });
+    }
 
     counter += temp_results.len() as u64;
 
     let mut locked_index = index.lock().unwrap();
-    while let Some((value, address)) = temp_results.pop() {
-        locked_index.insert(value, address);
+    while let Some((value, address, included)) = temp_results.pop() {
+        insert(&mut locked_index, value, address, included, split_by);
+    }
+
+    if fail_fast {
+        if let Some(error) = malformed(&locked_index) {
+            let error: Box<dyn Error + Send + Sync> = error.into();
+            return Err(error);
+        }
     }
 
     Ok(counter)
 }
+
+/// Insert a value, or each of its elements under the same address when splitting
+fn insert(
+    index: &mut CsvIndexType,
+    value: Vec<u8>,
+    address: Address,
+    included: Option<Vec<Vec<u8>>>,
+    split_by: Option<&[u8]>,
+) {
+    if let Some(values) = included {
+        index.include(address.offset, values);
+    }
+
+    match split_by {
+        None => index.insert(value, address),
+        Some(separator) => {
+            let elements = elements(&value, separator);
+            if elements.is_empty() {
+                // no elements, a null like any other empty value
+                index.insert(vec![], address);
+            } else {
+                for element in elements {
+                    index.insert(element, address.clone());
+                }
+            }
+        }
+    }
+}
+
+/// The distinct non-empty elements of a multi-valued field
+fn elements(value: &[u8], separator: &[u8]) -> Vec<Vec<u8>> {
+    let mut elements: Vec<Vec<u8>> = vec![];
+    let mut rest = value;
+    loop {
+        let end = rest
+            .windows(separator.len())
+            .position(|window| window == separator);
+        let element = &rest[..end.unwrap_or(rest.len())];
+        if !element.is_empty() && !elements.iter().any(|e| e.as_slice() == element) {
+            elements.push(element.to_vec());
+        }
+        match end {
+            Some(end) => rest = &rest[end + separator.len()..],
+            None => return elements,
+        }
+    }
+}
+
+fn malformed(index: &CsvIndexType) -> Option<String> {
+    let nulls = index.nulls()?;
+    let (address, raw) = nulls.samples.first()?;
+
+    Some(format!(
+        "Malformed value {:?} in record at byte {} (use --errors-to FILE to list all)",
+        String::from_utf8_lossy(raw),
+        address.offset
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_files::CsvFile;
+    use std::io::Cursor;
+
+    fn address(offset: u64) -> Address {
+        Address { offset, length: 1 }
+    }
+
+    #[test]
+    fn test_elements() {
+        assert_eq!(
+            elements(b"a;bc;;a;d", b";"),
+            vec![b"a".to_vec(), b"bc".to_vec(), b"d".to_vec()]
+        );
+        assert_eq!(elements(b"a, b", b", "), vec![b"a".to_vec(), b"b".to_vec()]);
+        assert_eq!(elements(b"abc", b";"), vec![b"abc".to_vec()]);
+        assert!(elements(b";;", b";").is_empty());
+        assert!(elements(b"", b";").is_empty());
+    }
+
+    #[test]
+    fn test_check_sparse() {
+        let sorted = IndexOptions {
+            sorted: Some(100),
+            ..Default::default()
+        };
+        assert!(check_sparse(&sorted).is_ok());
+
+        let empty = IndexOptions {
+            sorted: Some(0),
+            ..Default::default()
+        };
+        assert!(check_sparse(&empty).is_err());
+
+        let partial = IndexOptions {
+            conditions: vec!["col2 eq active".to_owned()],
+            ..sorted.clone()
+        };
+        assert!(check_sparse(&partial).is_err());
+
+        let multi_valued = IndexOptions {
+            split_by: Some(";".to_owned()),
+            ..sorted.clone()
+        };
+        assert!(check_sparse(&multi_valued).is_err());
+
+        let covering = IndexOptions {
+            include: vec![1],
+            ..sorted
+        };
+        assert!(check_sparse(&covering).is_err());
+    }
+
+    #[test]
+    fn test_line_numbers() {
+        // lines of two bytes, across several buffers of 64 KiB
+        let input = b"x\n".repeat(70_000);
+        let offsets = [
+            0, 2, 3, 65_534, 65_536, 65_538, 131_072, 139_998, 140_000, 150_000,
+        ];
+        assert_eq!(
+            line_numbers(Cursor::new(input), &offsets).unwrap(),
+            vec![1, 2, 2, 32_768, 32_769, 32_770, 65_537, 70_000, 70_001, 70_001]
+        );
+
+        assert!(line_numbers(Cursor::new(b"a\nb\n"), &[])
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_write_rejects() {
+        let input = CsvFile::new("index_rejects", b"id,n\n1,x\n2,5\n3,\"y\nz\"\n4,w\n");
+        let output = CsvFile::new("index_rejects_output", b"");
+
+        let rejects = vec![
+            (address(21), b"w".to_vec()),
+            (address(5), b"x".to_vec()),
+            (address(13), b"y\nz".to_vec()),
+        ];
+        write_rejects(input.path(), output.path(), &rejects).unwrap();
+
+        // sorted by offset, with the line where the record starts
+        assert_eq!(
+            std::fs::read_to_string(output.path()).unwrap(),
+            "offset,line,value\n5,2,x\n13,4,\"y\nz\"\n21,6,w\n"
+        );
+    }
+
+    #[test]
+    fn test_malformed() {
+        let options = IndexOptions::default();
+
+        let mut index = CsvIndexType::try_new("int", &options).unwrap();
+        index.insert(b"5".to_vec(), address(0));
+        index.insert(vec![], address(10));
+        assert_eq!(malformed(&index), None);
+
+        index.insert(b"five".to_vec(), address(20));
+        index.insert(b"six".to_vec(), address(30));
+        assert_eq!(
+            malformed(&index).unwrap(),
+            "Malformed value \"five\" in record at byte 20 (use --errors-to FILE to list all)"
+        );
+
+        // str indexes accept any value
+        let mut index = CsvIndexType::try_new("str", &options).unwrap();
+        index.insert(b"five".to_vec(), address(0));
+        assert_eq!(malformed(&index), None);
+    }
+}
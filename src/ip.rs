@@ -0,0 +1,76 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// IPv4 addresses are keyed as IPv4-mapped IPv6 addresses (::ffff:a.b.c.d)
+const IPV4_MAPPED: u128 = 0xffff_0000_0000;
+
+/// Key of an IPv4 or IPv6 address, so that both sort in a single order
+pub fn parse(value: &str) -> Option<u128> {
+    match value.trim().parse().ok()? {
+        IpAddr::V4(ip) => Some(IPV4_MAPPED | u128::from(u32::from(ip))),
+        IpAddr::V6(ip) => Some(u128::from(ip)),
+    }
+}
+
+pub fn format(key: u128) -> String {
+    if key >> 32 == IPV4_MAPPED >> 32 {
+        Ipv4Addr::from(key as u32).to_string()
+    } else {
+        Ipv6Addr::from(key).to_string()
+    }
+}
+
+/// First and last key of a network like 10.0.0.0/8 or 2001:db8::/32
+pub fn cidr(value: &str) -> Option<(u128, u128)> {
+    let mut parts = value.trim().splitn(2, '/');
+    let address = parts.next()?;
+    let prefix: u32 = parts.next()?.parse().ok()?;
+
+    // the prefix of an IPv4 network counts from the start of the mapped address
+    let prefix = match address.parse().ok()? {
+        IpAddr::V4(_) if prefix <= 32 => prefix + 96,
+        IpAddr::V6(_) if prefix <= 128 => prefix,
+        _ => return None,
+    };
+
+    let host_mask = u128::max_value().checked_shr(prefix).unwrap_or(0);
+    let first = parse(address)? & !host_mask;
+
+    Some((first, first | host_mask))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_format() {
+        for ip in &["10.0.0.1", "255.255.255.255", "2001:db8::1", "::1", "::"] {
+            assert_eq!(format(parse(ip).unwrap()), *ip);
+        }
+        assert_eq!(parse("::ffff:10.0.0.1"), parse("10.0.0.1"));
+        assert!(parse("10.0.0.1") < parse("10.0.0.2"));
+        assert!(parse("9.255.255.255") < parse("10.0.0.0"));
+        assert_eq!(parse("10.0.0"), None);
+        assert_eq!(parse("10.0.0.0/8"), None);
+    }
+
+    #[test]
+    fn test_cidr() {
+        let (first, last) = cidr("10.0.0.0/8").unwrap();
+        assert_eq!(format(first), "10.0.0.0");
+        assert_eq!(format(last), "10.255.255.255");
+
+        let (first, last) = cidr("192.168.1.77/24").unwrap();
+        assert_eq!(format(first), "192.168.1.0");
+        assert_eq!(format(last), "192.168.1.255");
+
+        let (first, last) = cidr("2001:db8::/32").unwrap();
+        assert_eq!(format(first), "2001:db8::");
+        assert_eq!(format(last), "2001:db8:ffff:ffff:ffff:ffff:ffff:ffff");
+
+        assert_eq!(cidr("10.0.0.1/32").map(|(f, l)| f == l), Some(true));
+        assert_eq!(cidr("::/0"), Some((0, u128::max_value())));
+        assert_eq!(cidr("10.0.0.0/33"), None);
+        assert_eq!(cidr("10.0.0.0"), None);
+    }
+}
@@ -1,22 +1,73 @@
 mod address;
+mod agg;
 mod bits;
 mod chunked_map;
 mod csv_index;
 mod csv_reader;
+mod datetime;
+mod decimal;
+mod expression;
 mod filter;
+mod fuzzy;
+mod group_by;
+mod hash;
 mod index;
+mod ip;
+mod normalize;
+mod number;
+mod predicate;
+mod printer;
 mod range;
+mod search;
+mod sparse;
+#[cfg(test)]
+mod test_files;
+mod text;
 mod toc;
+mod trigram;
 mod unsafe_float;
+mod uuid;
+mod values;
 
 use env_logger::Env;
 
 use clap::{crate_authors, crate_name, crate_version, value_t};
-use clap::{App, Arg, SubCommand};
+use clap::{App, Arg, ArgMatches, SubCommand};
 
 use std::error::Error;
 use std::fs::File;
 
+/// Parse the optional `--range OP VALUE [VALUE2]` argument into a filter
+fn range_filter<'a>(
+    matches: &'a ArgMatches,
+    column: &'a expression::Expression,
+) -> Result<Option<filter::Filter<'a>>, Box<dyn Error>> {
+    match matches.values_of("RANGE") {
+        Some(mut values) => {
+            let op = filter::Operator::from(values.next().expect("OP is required"))?;
+            let value = match values.next() {
+                Some(value) => value,
+                None if !op.needs_value() => "",
+                None => Err("--range needs a value for this operator")?,
+            };
+            let value2 = values.next().unwrap_or("");
+            Ok(Some(filter::Filter::from(op, value, value2, column)))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Parse a list of column numbers like `4,7` (starting at 1) into column indices
+fn column_list(columns: &str) -> Result<Vec<usize>, Box<dyn Error>> {
+    columns
+        .split(',')
+        .map(|column| match column.trim().parse::<usize>() {
+            Ok(n) if n > 0 => Ok(n - 1),
+            _ => Err(format!("Invalid column number {:?}, columns start at 1", column).into()),
+        })
+        .collect()
+}
+
 fn main() -> Result<(), Box<Error>> {
     let matches = App::new(crate_name!())
         .version(crate_version!())
@@ -48,13 +99,144 @@ fn main() -> Result<(), Box<Error>> {
                     Arg::with_name("COLUMN")
                         .required(true)
                         .index(1)
-                        .help("Column number (starts at 1)"),
+                        .help("Column number (starts at 1), or key expression (e.g. 'month(3)')"),
                 )
                 .arg(
                     Arg::with_name("TYPE")
                         .required(false)
                         .index(2)
-                        .help("Type (str(default), int, float)"),
+                        .help(
+                            "Type (str(default), int, u64, i128, u128, hex, float, decimal, date, datetime, timestamp, ip, uuid, trigram, text, hash)",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("STRICT")
+                        .long("strict")
+                        .help("Fail on values that do not parse as the given type"),
+                )
+                .arg(
+                    Arg::with_name("ERRORS_TO")
+                        .long("errors-to")
+                        .value_name("FILE")
+                        .takes_value(true)
+                        .requires("STRICT")
+                        .help("With --strict, write malformed records to FILE instead of failing"),
+                )
+                .arg(
+                    Arg::with_name("TIME_FORMAT")
+                        .long("time-format")
+                        .value_name("FORMAT")
+                        .takes_value(true)
+                        .help("Format of date/time values (iso, rfc2822, epoch, epoch-ms or e.g. %d/%m/%Y)"),
+                )
+                .arg(
+                    Arg::with_name("TZ")
+                        .long("tz")
+                        .takes_value(true)
+                        .help("Fixed offset of date/time values without offset (UTC(default) or e.g. +02:00), no named zones or DST"),
+                )
+                .arg(
+                    Arg::with_name("SCALE")
+                        .long("scale")
+                        .takes_value(true)
+                        .help("Number of fraction digits of a decimal column (detected when omitted)"),
+                )
+                .arg(
+                    Arg::with_name("THOUSANDS_SEP")
+                        .long("thousands-sep")
+                        .value_name("CHAR")
+                        .takes_value(true)
+                        .help("Thousands separator of int and float values (e.g. , for 1,234)"),
+                )
+                .arg(
+                    Arg::with_name("DECIMAL_SEP")
+                        .long("decimal-sep")
+                        .value_name("CHAR")
+                        .takes_value(true)
+                        .help("Decimal separator of float values (.(default) or e.g. , for 1,5)"),
+                )
+                .arg(
+                    Arg::with_name("CURRENCY")
+                        .long("currency")
+                        .help("Strip currency symbols around int and float values"),
+                )
+                .arg(
+                    Arg::with_name("ACCOUNTING")
+                        .long("accounting")
+                        .help("Read int and float values in parentheses as negative"),
+                )
+                .arg(
+                    Arg::with_name("FOLD_CASE")
+                        .long("fold-case")
+                        .help("Index str values in lowercase, making lookups case-insensitive"),
+                )
+                .arg(
+                    Arg::with_name("TRIM")
+                        .long("trim")
+                        .help("Remove leading and trailing whitespace from str values"),
+                )
+                .arg(
+                    Arg::with_name("NORMALIZE")
+                        .long("normalize")
+                        .value_name("FORM")
+                        .takes_value(true)
+                        .help("Unicode normalization of str values (nfc or nfkc)"),
+                )
+                .arg(
+                    Arg::with_name("STRIP_ACCENTS")
+                        .long("strip-accents")
+                        .help("Remove accents from str values, e.g. é becomes e"),
+                )
+                .arg(
+                    Arg::with_name("REVERSE")
+                        .long("reverse")
+                        .help("Store str values reversed, for suf (ends with) queries"),
+                )
+                .arg(
+                    Arg::with_name("STEM")
+                        .long("stem")
+                        .help("Strip English word endings in a text index, e.g. connected becomes connect"),
+                )
+                .arg(
+                    Arg::with_name("STOPWORDS")
+                        .long("stopwords")
+                        .help("Leave common English words (the, of, ...) out of a text index"),
+                )
+                .arg(
+                    Arg::with_name("SPLIT_BY")
+                        .long("split-by")
+                        .value_name("SEP")
+                        .takes_value(true)
+                        .help("Index every element of a multi-valued column, e.g. --split-by ';' for a;b;c"),
+                )
+                .arg(
+                    Arg::with_name("WHERE")
+                        .long("where")
+                        .value_name("CONDITION")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("Only index the records matching a condition, e.g. --where 'col5 eq active'"),
+                )
+                .arg(
+                    Arg::with_name("INCLUDE")
+                        .long("include")
+                        .value_name("COLUMNS")
+                        .takes_value(true)
+                        .help("Store the values of these columns in the index, e.g. --include 4,7"),
+                )
+                .arg(
+                    Arg::with_name("SORTED")
+                        .long("sorted")
+                        .help("Build a sparse index for input sorted by the column, storing only the first key of every block"),
+                )
+                .arg(
+                    Arg::with_name("BLOCK_SIZE")
+                        .long("block-size")
+                        .value_name("N")
+                        .takes_value(true)
+                        .requires("SORTED")
+                        .help("Number of records per block of a sparse index (1000 by default)"),
                 ),
         )
         .subcommand(
@@ -64,25 +246,130 @@ fn main() -> Result<(), Box<Error>> {
                     Arg::with_name("COLUMN")
                         .required(true)
                         .index(1)
-                        .help("Column number (starts at 1)"),
+                        .help("Column number (starts at 1), or indexed key expression"),
                 )
                 .arg(
                     Arg::with_name("OP")
                         .required(true)
                         .index(2)
-                        .help("Operator (eq, lt, le, gt, ge, in, sw)"),
+                        .help("Operator (eq, lt, le, gt, ge, in, pre, not-pre, suf, contains, regex, match, fuzzy, is-null, not-null, cidr)"),
                 )
                 .arg(
                     Arg::with_name("VALUE")
-                        .required(true)
+                        .required(false)
                         .index(3)
-                        .help("Value"),
+                        .help("Value (not needed for is-null, not-null)"),
                 )
                 .arg(
                     Arg::with_name("VALUE2")
                         .required(false)
                         .index(4)
-                        .help("Value2 (when operator is `in`)"),
+                        .help("Value2 (when operator is `in`, or the max edit distance of `fuzzy`)"),
+                )
+                .arg(
+                    Arg::with_name("SELECT")
+                        .long("select")
+                        .value_name("COLUMNS")
+                        .takes_value(true)
+                        .help("Only print these columns, read from the index when it includes them (e.g. --select 4,7)"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("search")
+                .about("Find records by binary search in a file sorted by a column, without an index")
+                .arg(
+                    Arg::with_name("COLUMN")
+                        .required(true)
+                        .index(1)
+                        .help("Column number (starts at 1), or key expression the file is sorted by"),
+                )
+                .arg(
+                    Arg::with_name("VALUE")
+                        .required(true)
+                        .index(2)
+                        .help("Value, or the lowest value when VALUE2 is given"),
+                )
+                .arg(
+                    Arg::with_name("VALUE2")
+                        .required(false)
+                        .index(3)
+                        .help("Highest value of the range (inclusive)"),
+                )
+                .arg(
+                    Arg::with_name("PREFIX")
+                        .long("prefix")
+                        .conflicts_with_all(&["VALUE2", "NUMERIC"])
+                        .help("Find all values starting with VALUE"),
+                )
+                .arg(
+                    Arg::with_name("NUMERIC")
+                        .long("numeric")
+                        .help("Compare values as numbers, for a file sorted numerically"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("values")
+                .about("List distinct values of a column with their counts")
+                .arg(
+                    Arg::with_name("COLUMN")
+                        .required(true)
+                        .index(1)
+                        .help("Column number (starts at 1), or indexed key expression"),
+                )
+                .arg(
+                    Arg::with_name("TOP")
+                        .long("top")
+                        .value_name("N")
+                        .takes_value(true)
+                        .help("Only show the N most frequent values"),
+                )
+                .arg(
+                    Arg::with_name("RANGE")
+                        .long("range")
+                        .value_name("OP VALUE [VALUE2]")
+                        .min_values(1)
+                        .max_values(3)
+                        .help("Only show values matching a filter (e.g. --range ge 10, --range not-null)"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("agg")
+                .about("Aggregate an int or float column (count, sum, min, max, mean, quantiles)")
+                .arg(
+                    Arg::with_name("COLUMN")
+                        .required(true)
+                        .index(1)
+                        .help("Column number (starts at 1), or indexed key expression"),
+                )
+                .arg(
+                    Arg::with_name("RANGE")
+                        .long("range")
+                        .value_name("OP VALUE [VALUE2]")
+                        .min_values(1)
+                        .max_values(3)
+                        .help("Only aggregate values matching a filter (e.g. --range ge 10, --range not-null)"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("groupby")
+                .about("Count records per combination of values of two indexed columns")
+                .arg(
+                    Arg::with_name("COLUMN")
+                        .required(true)
+                        .index(1)
+                        .help("Column number (starts at 1), or indexed key expression"),
+                )
+                .arg(
+                    Arg::with_name("COLUMN2")
+                        .required(true)
+                        .index(2)
+                        .help("Second column number (starts at 1), or indexed key expression"),
+                )
+                .arg(
+                    Arg::with_name("FORMAT")
+                        .long("format")
+                        .takes_value(true)
+                        .help("Output format (csv(default), json)"),
                 ),
         )
         .get_matches();
@@ -103,8 +390,7 @@ fn main() -> Result<(), Box<Error>> {
     let threads = value_t!(matches.value_of("THREADS"), u64).unwrap_or(2);
 
     if let Some(matches) = matches.subcommand_matches("index") {
-        let column = value_t!(matches.value_of("COLUMN"), usize).unwrap_or_else(|e| e.exit());
-        let column = column - 1; // index starts at 1
+        let column = expression::Expression::parse(matches.value_of("COLUMN").unwrap())?;
 
         let csv_type = matches.value_of("TYPE").unwrap_or("STR");
 
@@ -112,9 +398,47 @@ fn main() -> Result<(), Box<Error>> {
             panic!("Thread count must be larger than 0");
         }
 
-        let (index, length) = index::index(&filename, column, &csv_type, threads)?;
+        let options = index::IndexOptions {
+            strict: matches.is_present("STRICT"),
+            errors_to: matches.value_of("ERRORS_TO").map(|f| f.to_owned()),
+            time_format: matches.value_of("TIME_FORMAT").map(|f| f.to_owned()),
+            tz: matches.value_of("TZ").map(|tz| tz.to_owned()),
+            scale: if matches.is_present("SCALE") {
+                Some(value_t!(matches.value_of("SCALE"), u32).unwrap_or_else(|e| e.exit()))
+            } else {
+                None
+            },
+            thousands_sep: matches.value_of("THOUSANDS_SEP").map(|sep| sep.to_owned()),
+            decimal_sep: matches.value_of("DECIMAL_SEP").map(|sep| sep.to_owned()),
+            currency: matches.is_present("CURRENCY"),
+            accounting: matches.is_present("ACCOUNTING"),
+            fold_case: matches.is_present("FOLD_CASE"),
+            trim: matches.is_present("TRIM"),
+            normalize: matches.value_of("NORMALIZE").map(|form| form.to_owned()),
+            strip_accents: matches.is_present("STRIP_ACCENTS"),
+            reverse: matches.is_present("REVERSE"),
+            stem: matches.is_present("STEM"),
+            stopwords: matches.is_present("STOPWORDS"),
+            split_by: matches.value_of("SPLIT_BY").map(|sep| sep.to_owned()),
+            conditions: matches.values_of("WHERE").map_or(vec![], |values| {
+                values.map(|value| value.to_owned()).collect()
+            }),
+            include: match matches.value_of("INCLUDE") {
+                Some(columns) => column_list(columns)?,
+                None => vec![],
+            },
+            sorted: if matches.is_present("BLOCK_SIZE") {
+                Some(value_t!(matches.value_of("BLOCK_SIZE"), usize).unwrap_or_else(|e| e.exit()))
+            } else if matches.is_present("SORTED") {
+                Some(sparse::BLOCK_SIZE)
+            } else {
+                None
+            },
+        };
+
+        let (index, length) = index::index(&filename, &column, &csv_type, threads, &options)?;
 
-        let fh = File::create(format!("{}.index.{}", filename, column + 1))?;
+        let fh = File::create(column.index_path(&filename))?;
         index.serialize(fh, length)?;
 
         return Ok(());
@@ -123,25 +447,100 @@ fn main() -> Result<(), Box<Error>> {
     if let Some(matches) = matches.subcommand_matches("filter") {
         let mut file = File::open(filename.clone())?;
 
-        let column = value_t!(matches.value_of("COLUMN"), usize).unwrap_or_else(|e| e.exit());
-        let column = column - 1; // index starts at 1
+        let column = expression::Expression::parse(matches.value_of("COLUMN").unwrap())?;
+
+        let op_str = matches.value_of("OP").expect("required arg cannot be None");
+        let op = filter::Operator::from(op_str)?;
+
+        let value = match matches.value_of("VALUE") {
+            Some(value) => value,
+            None if !op.needs_value() => "",
+            None => Err("VALUE is required for this operator")?,
+        };
+
+        let value2 = matches.value_of("VALUE2").unwrap_or("");
+
+        let filter = filter::Filter::from(op, &value, &value2, &column);
+
+        let select = match matches.value_of("SELECT") {
+            Some(columns) => Some(column_list(columns)?),
+            None => None,
+        };
+
+        let stdout = std::io::stdout();
+        let writer = stdout.lock();
+
+        return filter.execute(&mut file, &filename, writer, select);
+    }
+
+    if let Some(matches) = matches.subcommand_matches("search") {
+        let column = expression::Expression::parse(matches.value_of("COLUMN").unwrap())?;
 
         let value = matches
             .value_of("VALUE")
             .expect("required arg cannot be None");
+        let value2 = matches.value_of("VALUE2");
 
-        let value2 = matches.value_of("VALUE2").unwrap_or("");
+        let search = search::Search::from(
+            &column,
+            value,
+            value2,
+            matches.is_present("PREFIX"),
+            matches.is_present("NUMERIC"),
+        );
 
-        let op_str = matches.value_of("OP").expect("required arg cannot be None");
-        let op = filter::Operator::from(op_str)?;
+        let stdout = std::io::stdout();
+        let writer = stdout.lock();
+
+        return search.execute(&filename, writer);
+    }
+
+    if let Some(matches) = matches.subcommand_matches("values") {
+        let column = expression::Expression::parse(matches.value_of("COLUMN").unwrap())?;
+
+        let top = if matches.is_present("TOP") {
+            Some(value_t!(matches.value_of("TOP"), usize).unwrap_or_else(|e| e.exit()))
+        } else {
+            None
+        };
+
+        let range = range_filter(matches, &column)?;
+
+        let values = values::Values::from(&column, range, top);
+
+        let stdout = std::io::stdout();
+        let writer = stdout.lock();
+
+        return values.execute(&filename, writer);
+    }
+
+    if let Some(matches) = matches.subcommand_matches("agg") {
+        let column = expression::Expression::parse(matches.value_of("COLUMN").unwrap())?;
+
+        let range = range_filter(matches, &column)?;
+
+        let agg = agg::Agg::from(&column, range);
+
+        let stdout = std::io::stdout();
+        let writer = stdout.lock();
+
+        return agg.execute(&filename, writer);
+    }
+
+    if let Some(matches) = matches.subcommand_matches("groupby") {
+        let column = expression::Expression::parse(matches.value_of("COLUMN").unwrap())?;
+
+        let column2 = expression::Expression::parse(matches.value_of("COLUMN2").unwrap())?;
+
+        let format = group_by::Format::from(matches.value_of("FORMAT").unwrap_or("CSV"))?;
 
-        let filter = filter::Filter::from(op, &value, &value2, column);
+        let group_by = group_by::GroupBy::from(column, column2, format);
 
         let stdout = std::io::stdout();
         let writer = stdout.lock();
 
-        return filter.execute(&mut file, &filename, writer);
+        return group_by.execute(&filename, writer);
     }
 
-    Err("Use one of the subcommands (index, filter, ..)")?
+    Err("Use one of the subcommands (index, filter, search, values, agg, groupby, ..)")?
 }
@@ -0,0 +1,205 @@
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Form {
+    /// Canonical composition, so é is always stored as a single code point
+    NFC,
+    /// Compatibility composition, also mapping e.g. ﬁ to fi and ² to 2
+    NFKC,
+}
+
+/// How the keys of a str index are derived from the values, applied to filter values alike
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Normalization {
+    fold_case: bool,
+    trim: bool,
+    form: Option<Form>,
+    strip_accents: bool,
+    /// store the bytes of the keys in reverse, so a suffix becomes a prefix
+    reverse: bool,
+}
+
+impl Normalization {
+    pub fn try_new(
+        fold_case: bool,
+        trim: bool,
+        form: Option<&str>,
+        strip_accents: bool,
+        reverse: bool,
+    ) -> Result<Self, &'static str> {
+        let form = match form.map(|form| form.to_uppercase()) {
+            None => None,
+            Some(ref form) if form == "NFC" => Some(Form::NFC),
+            Some(ref form) if form == "NFKC" => Some(Form::NFKC),
+            Some(_) => return Err("Unknown normalization form (use nfc or nfkc)"),
+        };
+
+        Ok(Normalization {
+            fold_case,
+            trim,
+            form,
+            strip_accents,
+            reverse,
+        })
+    }
+
+    pub fn is_identity(&self) -> bool {
+        *self == Normalization::default()
+    }
+
+    pub fn folds_case(&self) -> bool {
+        self.fold_case
+    }
+
+    pub fn is_reversed(&self) -> bool {
+        self.reverse
+    }
+
+    /// Key of a value, values that are not valid UTF-8 are only trimmed
+    pub fn key<'a>(&self, value: &'a [u8]) -> Cow<'a, [u8]> {
+        let value = if self.trim { trim(value) } else { value };
+        self.reversed(self.normalize(value))
+    }
+
+    /// Normalized form of a prefix, where trailing whitespace is kept as part of the prefix
+    pub fn prefix<'a>(&self, value: &'a [u8]) -> Cow<'a, [u8]> {
+        let value = if self.trim { trim_start(value) } else { value };
+        self.normalize(value)
+    }
+
+    /// Normalized form of a part of a value, kept as is apart from the normalization
+    pub fn fragment<'a>(&self, value: &'a [u8]) -> Cow<'a, [u8]> {
+        self.normalize(value)
+    }
+
+    /// Key prefix of the values ending with a suffix, on a reversed index
+    pub fn suffix<'a>(&self, value: &'a [u8]) -> Cow<'a, [u8]> {
+        let value = if self.trim { trim_end(value) } else { value };
+        self.reversed(self.normalize(value))
+    }
+
+    /// The (normalized) value a key was made of
+    pub fn format_key(&self, key: &[u8]) -> Vec<u8> {
+        self.reversed(Cow::Borrowed(key)).into_owned()
+    }
+
+    fn reversed<'a>(&self, key: Cow<'a, [u8]>) -> Cow<'a, [u8]> {
+        if self.reverse {
+            Cow::Owned(key.iter().rev().cloned().collect())
+        } else {
+            key
+        }
+    }
+
+    fn normalize<'a>(&self, value: &'a [u8]) -> Cow<'a, [u8]> {
+        let unicode = self.fold_case || self.form.is_some() || self.strip_accents;
+        let text = match std::str::from_utf8(value) {
+            Ok(text) if unicode => text,
+            _ => return Cow::Borrowed(value),
+        };
+
+        let mut text: String = match (self.strip_accents, self.form) {
+            (true, Some(Form::NFKC)) => text
+                .nfkd()
+                .filter(|c| !is_combining_mark(*c))
+                .nfc()
+                .collect(),
+            (true, _) => text
+                .nfd()
+                .filter(|c| !is_combining_mark(*c))
+                .nfc()
+                .collect(),
+            (false, Some(Form::NFKC)) => text.nfkc().collect(),
+            (false, Some(Form::NFC)) => text.nfc().collect(),
+            (false, None) => text.to_owned(),
+        };
+
+        if self.fold_case {
+            text = text.to_lowercase();
+        }
+
+        Cow::Owned(text.into_bytes())
+    }
+}
+
+fn trim(value: &[u8]) -> &[u8] {
+    trim_end(trim_start(value))
+}
+
+fn trim_end(value: &[u8]) -> &[u8] {
+    let end = value
+        .iter()
+        .rposition(|b| !b.is_ascii_whitespace())
+        .map_or(0, |end| end + 1);
+
+    &value[..end]
+}
+
+fn trim_start(value: &[u8]) -> &[u8] {
+    let start = value
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(value.len());
+
+    &value[start..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default() {
+        let normalization = Normalization::default();
+        assert_eq!(&*normalization.key(b" Amsterdam "), b" Amsterdam ");
+    }
+
+    #[test]
+    fn test_fold_case_and_trim() {
+        let normalization = Normalization::try_new(true, true, None, false, false).unwrap();
+        assert_eq!(&*normalization.key(b" Amsterdam \t"), b"amsterdam");
+        assert_eq!(&*normalization.key(b"   "), b"");
+        assert_eq!(&*normalization.key(b" \xff "), b"\xff");
+        assert_eq!(&*normalization.prefix(b" New Y"), b"new y");
+        assert_eq!(&*normalization.prefix(b" New "), b"new ");
+    }
+
+    #[test]
+    fn test_reverse() {
+        let normalization = Normalization::try_new(true, true, None, false, true).unwrap();
+        assert_eq!(
+            &*normalization.key(b" Info@Example.GOV"),
+            b"vog.elpmaxe@ofni"
+        );
+        assert_eq!(&*normalization.suffix(b" .Gov "), b"vog. ");
+        assert_eq!(
+            normalization.format_key(b"vog.elpmaxe@ofni"),
+            b"info@example.gov"
+        );
+    }
+
+    #[test]
+    fn test_forms() {
+        let decomposed = "Cafe\u{301}";
+        let nfc = Normalization::try_new(false, false, Some("nfc"), false, false).unwrap();
+        assert_eq!(&*nfc.key(decomposed.as_bytes()), "Caf\u{e9}".as_bytes());
+
+        let nfkc = Normalization::try_new(false, false, Some("NFKC"), false, false).unwrap();
+        assert_eq!(&*nfkc.key("\u{fb01}le".as_bytes()), b"file");
+
+        assert!(Normalization::try_new(false, false, Some("nfd"), false, false).is_err());
+    }
+
+    #[test]
+    fn test_strip_accents() {
+        let normalization = Normalization::try_new(true, false, None, true, false).unwrap();
+        assert_eq!(
+            &*normalization.key("Crème Brûlée".as_bytes()),
+            b"creme brulee"
+        );
+        assert_eq!(&*normalization.key("Caf\u{e9}".as_bytes()), b"cafe");
+    }
+}
@@ -0,0 +1,204 @@
+use serde::{Deserialize, Serialize};
+
+/// Symbols removed around a value when currency stripping is enabled
+const CURRENCY_SYMBOLS: [char; 14] = [
+    '$', '€', '£', '¥', '₹', '₽', '₩', '₺', '₪', '₫', '฿', '₴', '₦', '¢',
+];
+
+/// How to read int and float values written for humans, like `€ 1.234,50` or `(45)`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NumberFormat {
+    /// separator between groups of three digits, removed before parsing
+    thousands: Option<char>,
+    /// separator between the integer and the fraction digits
+    decimal: char,
+    /// strip currency symbols around the value
+    currency: bool,
+    /// read values in parentheses as negative, e.g. (45) is -45
+    accounting: bool,
+}
+
+impl Default for NumberFormat {
+    fn default() -> Self {
+        NumberFormat {
+            thousands: None,
+            decimal: '.',
+            currency: false,
+            accounting: false,
+        }
+    }
+}
+
+impl NumberFormat {
+    pub fn try_new(
+        thousands: Option<&str>,
+        decimal: Option<&str>,
+        currency: bool,
+        accounting: bool,
+    ) -> Result<Self, &'static str> {
+        let thousands = match thousands {
+            Some(sep) => {
+                Some(separator(sep).ok_or("Thousands separator should be a single character")?)
+            }
+            None => None,
+        };
+        let decimal = match decimal {
+            Some(sep) => separator(sep).ok_or("Decimal separator should be a single character")?,
+            None => '.',
+        };
+        if thousands == Some(decimal) {
+            return Err("Thousands and decimal separator should differ");
+        }
+
+        Ok(NumberFormat {
+            thousands,
+            decimal,
+            currency,
+            accounting,
+        })
+    }
+
+    pub fn parse_int(&self, value: &[u8]) -> Option<i64> {
+        let value = std::str::from_utf8(value).ok()?;
+        if *self == NumberFormat::default() {
+            return value.parse().ok();
+        }
+        self.normalize(value)?.parse().ok()
+    }
+
+    pub fn parse_float(&self, value: &[u8]) -> Option<f64> {
+        let value = std::str::from_utf8(value).ok()?;
+        if *self == NumberFormat::default() {
+            return value.parse().ok();
+        }
+        self.normalize(value)?.parse().ok()
+    }
+
+    /// Read a filter value, written in the format of the index or as a plain number
+    pub fn parse_int_value(&self, value: &str) -> Option<i64> {
+        self.parse_int(value.as_bytes())
+            .or_else(|| value.trim().parse().ok())
+    }
+
+    /// Read a filter value, written in the format of the index or as a plain number
+    pub fn parse_float_value(&self, value: &str) -> Option<f64> {
+        self.parse_float(value.as_bytes())
+            .or_else(|| value.trim().parse().ok())
+    }
+
+    /// Rewrite a value in the notation of `str::parse`, e.g. `(1.234,50)` into `-1234.50`
+    fn normalize(&self, value: &str) -> Option<String> {
+        let mut value = self.strip(value);
+
+        let mut negative = false;
+        if self.accounting && value.starts_with('(') && value.ends_with(')') {
+            negative = true;
+            value = self.strip(&value[1..value.len() - 1]);
+        }
+
+        // the sign may precede a currency symbol, as in -$45
+        let mut sign = "";
+        if value.starts_with('-') || value.starts_with('+') {
+            if negative {
+                return None;
+            }
+            sign = &value[..1];
+            value = self.strip(&value[1..]);
+        }
+
+        let (int, fraction) = match value.find(self.decimal) {
+            Some(pos) => (&value[..pos], Some(&value[pos + self.decimal.len_utf8()..])),
+            None => (value, None),
+        };
+
+        let mut out = String::with_capacity(value.len() + 1);
+        out.push_str(if negative { "-" } else { sign });
+
+        match self.thousands {
+            Some(sep) if int.contains(sep) => {
+                for (i, group) in int.split(sep).enumerate() {
+                    let valid = if i == 0 {
+                        !group.is_empty() && group.len() <= 3
+                    } else {
+                        group.len() == 3
+                    };
+                    if !valid || !group.bytes().all(|b| b.is_ascii_digit()) {
+                        return None;
+                    }
+                    out.push_str(group);
+                }
+            }
+            _ => out.push_str(int),
+        }
+
+        if let Some(fraction) = fraction {
+            out.push('.');
+            out.push_str(fraction);
+        }
+
+        Some(out)
+    }
+
+    fn strip<'a>(&self, value: &'a str) -> &'a str {
+        value.trim_matches(|c: char| {
+            c.is_whitespace() || (self.currency && CURRENCY_SYMBOLS.contains(&c))
+        })
+    }
+}
+
+fn separator(sep: &str) -> Option<char> {
+    let mut chars = sep.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if !c.is_ascii_digit() => Some(c),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain() {
+        let format = NumberFormat::default();
+        assert_eq!(format.parse_int(b"-12"), Some(-12));
+        assert_eq!(format.parse_int(b"1,234"), None);
+        assert_eq!(format.parse_float(b"3.2e5"), Some(320_000.));
+    }
+
+    #[test]
+    fn test_separators() {
+        let en = NumberFormat::try_new(Some(","), None, false, false).unwrap();
+        assert_eq!(en.parse_float(b"1,234.50"), Some(1234.5));
+        assert_eq!(en.parse_int(b"-1,234,567"), Some(-1_234_567));
+        assert_eq!(en.parse_int(b"1,23"), None);
+        assert_eq!(en.parse_int(b"1234,567"), None);
+
+        let de = NumberFormat::try_new(Some("."), Some(","), false, false).unwrap();
+        assert_eq!(de.parse_float(b"1.234,50"), Some(1234.5));
+        assert_eq!(de.parse_float(b"3,2e5"), Some(320_000.));
+        assert_eq!(de.parse_int(b"1.234"), Some(1234));
+        assert_eq!(de.parse_float_value("1.5"), Some(1.5));
+    }
+
+    #[test]
+    fn test_currency_and_accounting() {
+        let format = NumberFormat::try_new(Some(","), None, true, true).unwrap();
+        assert_eq!(format.parse_int(b"\xe2\x82\xac 12"), Some(12));
+        assert_eq!(format.parse_float(b"$1,234.50"), Some(1234.5));
+        assert_eq!(format.parse_float(b"-$45"), Some(-45.));
+        assert_eq!(format.parse_int(b"(45)"), Some(-45));
+        assert_eq!(format.parse_int(b"($ 1,000)"), Some(-1000));
+        assert_eq!(format.parse_int(b"(-45)"), None);
+
+        let plain = NumberFormat::default();
+        assert_eq!(plain.parse_int(b"(45)"), None);
+    }
+
+    #[test]
+    fn test_invalid_separators() {
+        assert!(NumberFormat::try_new(Some(","), Some(","), false, false).is_err());
+        assert!(NumberFormat::try_new(Some("ab"), None, false, false).is_err());
+        assert!(NumberFormat::try_new(None, Some("1"), false, false).is_err());
+    }
+}
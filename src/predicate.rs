@@ -0,0 +1,159 @@
+use crate::expression::Expression;
+use crate::filter::Operator;
+
+use csv::ByteRecord;
+
+use std::cmp::Ordering;
+use std::error::Error;
+
+/// Condition on a record, deciding whether it goes into a partial index
+pub struct Predicate {
+    key: Expression,
+    op: Operator,
+    value: Vec<u8>,
+    value2: Vec<u8>,
+}
+
+impl Predicate {
+    /// Parse e.g. `col5 eq active`, `3 in 10 20` or `lower(2) pre a`
+    pub fn parse(text: &str) -> Result<Self, Box<dyn Error>> {
+        let text = text.trim();
+        let offset = |word: &str| word.as_ptr() as usize - text.as_ptr() as usize;
+
+        // the column can be an expression with spaces, so the operator is the first word after
+        // the start of a valid one
+        let (key, op, value) = text
+            .split_whitespace()
+            .skip(1)
+            .find_map(|word| {
+                let op = Operator::from(word).ok()?;
+                let column = text[..offset(word)].trim_end();
+                let key = Expression::parse(column.strip_prefix("col").unwrap_or(column)).ok()?;
+                Some((key, op, text[offset(word) + word.len()..].trim()))
+            })
+            .ok_or("--where needs a column and operator, e.g. `col5 eq active`")?;
+
+        let (value, value2) = match op {
+            Operator::ISNULL | Operator::NOTNULL => ("", ""),
+            Operator::IN => {
+                let mut values = value.split_whitespace();
+                match (values.next(), values.next(), values.next()) {
+                    (Some(value), Some(value2), None) => (value, value2),
+                    _ => Err("in needs two values in --where")?,
+                }
+            }
+            Operator::EQ
+            | Operator::LT
+            | Operator::LE
+            | Operator::GT
+            | Operator::GE
+            | Operator::PRE
+            | Operator::NOTPRE
+            | Operator::SUF
+            | Operator::CONTAINS => {
+                if value.is_empty() {
+                    Err("--where needs a value for this operator")?
+                }
+                (value, "")
+            }
+            _ => Err("only eq, lt, le, gt, ge, in, pre, not-pre, suf, contains, is-null and not-null can be used in --where")?,
+        };
+
+        Ok(Predicate {
+            key,
+            op,
+            value: value.as_bytes().to_vec(),
+            value2: value2.as_bytes().to_vec(),
+        })
+    }
+
+    pub fn matches(&self, record: &ByteRecord) -> bool {
+        let value = self.key.eval(record).unwrap_or_default();
+
+        // empty values are nulls, matched by is-null only
+        match self.op {
+            Operator::ISNULL => return value.is_empty(),
+            _ if value.is_empty() => return false,
+            _ => (),
+        }
+
+        match self.op {
+            Operator::EQ => compare(&value, &self.value) == Ordering::Equal,
+            Operator::LT => compare(&value, &self.value) == Ordering::Less,
+            Operator::LE => compare(&value, &self.value) != Ordering::Greater,
+            Operator::GT => compare(&value, &self.value) == Ordering::Greater,
+            Operator::GE => compare(&value, &self.value) != Ordering::Less,
+            Operator::IN => {
+                compare(&value, &self.value) != Ordering::Less
+                    && compare(&value, &self.value2) != Ordering::Greater
+            }
+            Operator::PRE => value.starts_with(&self.value),
+            Operator::NOTPRE => !value.starts_with(&self.value),
+            Operator::SUF => value.ends_with(&self.value),
+            Operator::CONTAINS => value
+                .windows(self.value.len())
+                .any(|window| window == &self.value[..]),
+            _ => true,
+        }
+    }
+}
+
+/// Compare as numbers when both sides are numbers, and as bytes otherwise
+fn compare(value: &[u8], other: &[u8]) -> Ordering {
+    let number = |bytes: &[u8]| {
+        std::str::from_utf8(bytes)
+            .ok()
+            .and_then(|text| text.trim().parse::<f64>().ok())
+    };
+
+    match (number(value), number(other)) {
+        (Some(a), Some(b)) if !a.is_nan() && !b.is_nan() => a.partial_cmp(&b).unwrap(),
+        _ => value.cmp(other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(predicate: &str, fields: &[&str]) -> bool {
+        let record = ByteRecord::from(fields.to_vec());
+        Predicate::parse(predicate).unwrap().matches(&record)
+    }
+
+    #[test]
+    fn test_parse() {
+        assert!(Predicate::parse("col5 eq active").is_ok());
+        assert!(Predicate::parse("lower(2) pre a").is_ok());
+        assert!(Predicate::parse("col5").is_err());
+        assert!(Predicate::parse("col5 eq").is_err());
+        assert!(Predicate::parse("col5 in 10").is_err());
+        assert!(Predicate::parse("col5 regex a").is_err());
+        assert!(Predicate::parse("col0 eq a").is_err());
+        assert!(Predicate::parse("col5  eq  active").is_ok());
+        assert!(Predicate::parse("lower( 2 ) pre a").is_ok());
+        assert!(Predicate::parse("concat(1,' eq ',2) eq a").is_ok());
+    }
+
+    #[test]
+    fn test_matches() {
+        let fields = ["7", "open", "Open Order", "", "100.5"];
+        assert!(matches("col2 eq open", &fields));
+        assert!(!matches("col2 eq closed", &fields));
+        assert!(matches("col3 eq Open Order", &fields));
+        assert!(matches("col3  eq  Open Order", &fields));
+        assert!(matches("lower( 3 ) pre open", &fields));
+        assert!(matches("concat(1,' eq ',2) eq 7 eq open", &fields));
+        assert!(matches("lower(3) contains order", &fields));
+        assert!(matches("col4 is-null", &fields));
+        assert!(matches("col9 is-null", &fields));
+        assert!(!matches("col4 lt z", &fields));
+        assert!(matches("col1 not-null", &fields));
+
+        // numbers compare as numbers, 7 < 10 even though "7" > "10"
+        assert!(matches("col1 lt 10", &fields));
+        assert!(matches("col5 in 100 200", &fields));
+        assert!(!matches("col5 gt 1000", &fields));
+        assert!(matches("col2 gt 10", &fields));
+    }
+}
@@ -0,0 +1,138 @@
+use crate::address::Address;
+use crate::csv_reader;
+
+use std::error::Error;
+use std::fs::File;
+use std::io::Write;
+
+enum Output<W: Write> {
+    /// Whole records, copied from the csv file as they are
+    Raw(W),
+    /// Selected columns, written as new csv records
+    Csv(Box<csv::Writer<W>>, Vec<usize>),
+}
+
+/// Writes the matching records, or only the selected columns of them
+pub struct Printer<'a, W: Write> {
+    file: &'a File,
+    output: Output<W>,
+
+    // positions of the selected columns among the values stored in the index, when it has all
+    covered: Option<Vec<usize>>,
+}
+
+impl<'a, W: Write> Printer<'a, W> {
+    pub fn new(file: &'a File, writer: W, select: Option<Vec<usize>>) -> Self {
+        let output = match select {
+            Some(columns) => Output::Csv(Box::new(csv::Writer::from_writer(writer)), columns),
+            None => Output::Raw(writer),
+        };
+
+        Printer {
+            file,
+            output,
+            covered: None,
+        }
+    }
+
+    /// The csv file the addresses point into
+    pub fn file(&self) -> &'a File {
+        self.file
+    }
+
+    /// Use the values of these columns stored in the index, instead of reading the csv file
+    pub fn cover(&mut self, included: &[usize]) {
+        self.covered = match &self.output {
+            Output::Raw(_) => None,
+            Output::Csv(_, select) => select
+                .iter()
+                .map(|column| included.iter().position(|c| c == column))
+                .collect(),
+        };
+    }
+
+    /// Print the record at an address, given the values the index stores for it
+    pub fn print(
+        &mut self,
+        address: &Address,
+        included: Option<&Vec<Vec<u8>>>,
+    ) -> Result<(), Box<dyn Error>> {
+        match (&mut self.output, &self.covered, included) {
+            (Output::Raw(writer), _, _) => address.print_record(writer, self.file),
+            (Output::Csv(wtr, _), Some(positions), Some(values)) => {
+                wtr.write_record(positions.iter().map(|position| &values[*position]))?;
+            }
+            (Output::Csv(..), _, _) => {
+                let record = address.read_record(self.file)?;
+                self.print_raw(&record)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Print a record that was already read from the csv file
+    pub fn print_raw(&mut self, record: &[u8]) -> Result<(), Box<dyn Error>> {
+        match &mut self.output {
+            Output::Raw(writer) => writer.write_all(record)?,
+            Output::Csv(wtr, select) => {
+                let parsed = csv_reader::parse_record(record).unwrap_or_default();
+                let fields = select
+                    .iter()
+                    .map(|column| parsed.get(*column).unwrap_or(b""));
+                wtr.write_record(fields)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<(), Box<dyn Error>> {
+        match self.output {
+            Output::Raw(mut writer) => writer.flush()?,
+            Output::Csv(mut wtr, _) => wtr.flush()?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_files::CsvFile;
+
+    // the record of bob starts at byte 26
+    const INPUT: &[u8] = b"id,name,city\n1,anna,paris\n2,bob,rome\n";
+
+    /// Print the record of bob, where the index includes the columns city and id
+    fn print(csv: &CsvFile, select: Option<Vec<usize>>, included: Option<&Vec<Vec<u8>>>) -> String {
+        let file = csv.open();
+        let address = Address {
+            offset: 26,
+            length: 11,
+        };
+
+        let mut output = vec![];
+        let mut printer = Printer::new(&file, &mut output, select);
+        printer.cover(&[2, 0]);
+        printer.print(&address, included).unwrap();
+        printer.finish().unwrap();
+
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_print() {
+        let csv = CsvFile::new("printer", INPUT);
+        // different from the csv file, to tell where the values were read
+        let included = vec![b"ROME".to_vec(), b"2".to_vec()];
+
+        assert_eq!(print(&csv, Some(vec![0, 2]), Some(&included)), "2,ROME\n");
+        assert_eq!(print(&csv, Some(vec![2]), Some(&included)), "ROME\n");
+
+        // columns the index does not include are read from the csv file
+        assert_eq!(print(&csv, Some(vec![0, 1]), Some(&included)), "2,bob\n");
+        assert_eq!(print(&csv, Some(vec![0, 2]), None), "2,rome\n");
+        assert_eq!(print(&csv, None, Some(&included)), "2,bob,rome\n");
+    }
+}
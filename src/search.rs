@@ -0,0 +1,210 @@
+use crate::address::Address;
+use crate::csv_reader::CsvReader;
+use crate::expression::Expression;
+
+use log::debug;
+use std::cmp::Ordering;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Read, Seek, Write};
+
+/// Lookup in a csv file sorted by a column, without an index
+pub struct Search<'a> {
+    column: &'a Expression,
+    value: &'a str,
+    value2: Option<&'a str>,
+    prefix: bool,
+    numeric: bool,
+}
+
+impl<'a> Search<'a> {
+    pub fn from(
+        column: &'a Expression,
+        value: &'a str,
+        value2: Option<&'a str>,
+        prefix: bool,
+        numeric: bool,
+    ) -> Self {
+        Search {
+            column,
+            value,
+            value2,
+            prefix,
+            numeric,
+        }
+    }
+
+    pub fn execute<W: Write>(&self, filename: &str, mut writer: W) -> Result<(), Box<dyn Error>> {
+        self.check_numbers()?;
+
+        let file = File::open(filename)?;
+        let length = file.metadata()?.len();
+
+        let addresses = self.find(&file, length)?;
+        debug!("{} matching records", addresses.len());
+
+        for address in addresses {
+            address.print_record(&mut writer, &file);
+        }
+
+        Ok(())
+    }
+
+    /// The values of a numeric search should be finite numbers
+    fn check_numbers(&self) -> Result<(), String> {
+        if !self.numeric {
+            return Ok(());
+        }
+
+        for value in Some(self.value).iter().chain(self.value2.iter()) {
+            match parse_number(value.as_bytes()) {
+                Some(number) if number.is_finite() => (),
+                _ => return Err(format!("Invalid number: {:?}", value)),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Records with a matching key, found by a binary search over the byte positions of the input
+    ///
+    /// Like `look`, every probe jumps to the start of the next line, so the records should not
+    /// contain line breaks.
+    fn find<R: Read + Seek + Clone>(&self, input: R, length: u64) -> io::Result<Vec<Address>> {
+        // key of the first record starting after a position, None beyond the last record
+        let key_after = |position| -> io::Result<_> {
+            let mut reader =
+                CsvReader::new(input.clone(), self.column.clone(), position, u64::MAX)?;
+            Ok(reader.next().map(|(_, key)| key))
+        };
+
+        // smallest position after which the first record does not sort before the value
+        let (mut low, mut high) = (0, length);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            match key_after(middle)? {
+                Some(key) if self.before(&key) => low = middle + 1,
+                _ => high = middle,
+            }
+        }
+        debug!("first candidate after byte {}", low);
+
+        let addresses = CsvReader::new(input, self.column.clone(), low, u64::MAX)?
+            .skip_while(|(_, key)| self.before(key))
+            .take_while(|(_, key)| !self.after(key))
+            .map(|(address, _)| address)
+            .collect();
+
+        Ok(addresses)
+    }
+
+    /// Whether a key sorts before the matching ones
+    fn before(&self, key: &[u8]) -> bool {
+        self.compare(key, self.value) == Ordering::Less
+    }
+
+    /// Whether a key sorts after the matching ones
+    fn after(&self, key: &[u8]) -> bool {
+        if self.prefix {
+            return !key.starts_with(self.value.as_bytes())
+                && self.compare(key, self.value) == Ordering::Greater;
+        }
+
+        let upper = self.value2.unwrap_or(self.value);
+        self.compare(key, upper) == Ordering::Greater
+    }
+
+    fn compare(&self, key: &[u8], value: &str) -> Ordering {
+        if !self.numeric {
+            return key.cmp(value.as_bytes());
+        }
+
+        // keys that are not numbers sort first, like the nulls of a numeric index
+        let number = parse_number(key);
+        number
+            .partial_cmp(&parse_number(value.as_bytes()))
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+/// A number to compare, where nan is not a number as it does not sort
+fn parse_number(value: &[u8]) -> Option<f64> {
+    let number: f64 = std::str::from_utf8(value).ok()?.trim().parse().ok()?;
+    if number.is_nan() {
+        return None;
+    }
+
+    Some(number)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const INPUT: &[u8] = b"id,name\n1,anna\n2,bob\n2,bobby\n5,carl\n10,dirk\n10,dora\n12,eve\n";
+
+    fn found(search: &Search) -> Vec<String> {
+        found_in(INPUT, search)
+    }
+
+    fn found_in(input: &[u8], search: &Search) -> Vec<String> {
+        search
+            .find(Cursor::new(input), input.len() as u64)
+            .unwrap()
+            .iter()
+            .map(|address| {
+                let start = address.offset as usize;
+                let end = start + address.length as usize;
+                String::from_utf8_lossy(&input[start..end])
+                    .trim()
+                    .to_owned()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_numeric() {
+        let id = Expression::Column(0);
+        let search = |value, value2| Search::from(&id, value, value2, false, true);
+
+        assert_eq!(found(&search("2", None)), vec!["2,bob", "2,bobby"]);
+        assert_eq!(found(&search("10", None)), vec!["10,dirk", "10,dora"]);
+        assert_eq!(found(&search("1", None)), vec!["1,anna"]);
+        assert_eq!(found(&search("12", None)), vec!["12,eve"]);
+        assert_eq!(found(&search("3", None)), Vec::<String>::new());
+        assert_eq!(found(&search("13", None)), Vec::<String>::new());
+        assert_eq!(
+            found(&search("3", Some("10"))),
+            vec!["5,carl", "10,dirk", "10,dora"]
+        );
+    }
+
+    #[test]
+    fn test_nan() {
+        let id = Expression::Column(0);
+        let search = |value| Search::from(&id, value, None, false, true);
+
+        assert!(search("nan").check_numbers().is_err());
+        assert!(search("inf").check_numbers().is_err());
+        assert!(search("1e3").check_numbers().is_ok());
+
+        // nan keys sort first, like other values that are not numbers
+        let input = b"id,name\nn/a,anna\nnan,bob\n1,carl\n2,dirk\n";
+        assert_eq!(found_in(input, &search("1")), vec!["1,carl"]);
+        assert_eq!(found_in(input, &search("2")), vec!["2,dirk"]);
+    }
+
+    #[test]
+    fn test_text() {
+        let name = Expression::Column(1);
+        let search = |value, prefix| Search::from(&name, value, None, prefix, false);
+
+        assert_eq!(found(&search("bob", false)), vec!["2,bob"]);
+        assert_eq!(found(&search("bob", true)), vec!["2,bob", "2,bobby"]);
+        assert_eq!(found(&search("d", true)), vec!["10,dirk", "10,dora"]);
+        assert_eq!(found(&search("a", true)), vec!["1,anna"]);
+        assert_eq!(found(&search("f", true)), Vec::<String>::new());
+        assert_eq!(found(&search("", true)).len(), 7);
+    }
+}
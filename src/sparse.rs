@@ -0,0 +1,201 @@
+use crate::address::Address;
+use crate::expression::Expression;
+use crate::printer::Printer;
+use crate::range::{ranges_overlap, Range};
+use crate::toc::Toc;
+
+use csv::{ByteRecord, ReaderBuilder};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use log::debug;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::Debug;
+use std::io::Write;
+
+use std::fs::File;
+use std::ops::Bound::{Included, Unbounded};
+use std::ops::RangeBounds;
+
+/// Number of records per block of a sparse index, unless given with --block-size
+pub const BLOCK_SIZE: usize = 1000;
+
+/// Whether the records are stored in the file in the order of their keys
+pub fn is_sorted<R: Ord>(map: &BTreeMap<R, Vec<Address>>) -> bool {
+    let mut end = None;
+    for addresses in map.values() {
+        let first = addresses.iter().map(|address| address.offset).min();
+        if first <= end {
+            return false;
+        }
+        end = addresses.iter().map(|address| address.offset).max();
+    }
+
+    true
+}
+
+/// Group the records into blocks of block_size records, stored under the key of their first record
+///
+/// The block of a key holds the records from its first one up to the end of its last one, so
+/// blocks never overlap when the file is sorted by the key.
+pub fn blocks<R: Ord + Clone>(
+    map: BTreeMap<R, Vec<Address>>,
+    block_size: usize,
+) -> Result<BTreeMap<R, Vec<Address>>, Box<dyn Error>> {
+    if !is_sorted(&map) {
+        Err("The input is not sorted by the key, a sparse index can not be built")?
+    }
+
+    let mut records: Vec<(R, Address)> = vec![];
+    for (key, mut addresses) in map {
+        addresses.sort_unstable_by_key(|address| address.offset);
+        records.extend(addresses.into_iter().map(|address| (key.clone(), address)));
+    }
+
+    let mut blocks = BTreeMap::new();
+    for block in records.chunks(block_size) {
+        let (first, start) = &block[0];
+        let (_, last) = &block[block.len() - 1];
+        let address = Address {
+            offset: start.offset,
+            length: last.offset + last.length - start.offset,
+        };
+        blocks
+            .entry(first.clone())
+            .or_insert_with(Vec::new)
+            .push(address);
+    }
+
+    Ok(blocks)
+}
+
+/// Print the records with a key in any of the ranges, scanning only the blocks that can hold them
+pub fn print_matches<R, P, W>(
+    toc: Toc<R>,
+    fh: &mut File,
+    key: &Expression,
+    ranges: Vec<Range<R>>,
+    parse: P,
+    printer: &mut Printer<W>,
+) -> Result<(), Box<dyn Error>>
+where
+    R: Ord + Serialize + DeserializeOwned + Clone + Debug,
+    P: Fn(&[u8]) -> Option<R>,
+    W: Write,
+{
+    let file = printer.file();
+
+    // the blocks of a sparse index are few, read them all to know where each one ends
+    let indexes = toc.get_index(fh, &(Unbounded, Unbounded))?;
+    let blocks: Vec<(&R, &Address)> = indexes
+        .iter()
+        .flat_map(|index| index.range((Unbounded, Unbounded)))
+        .flat_map(|(first, addresses)| addresses.iter().map(move |address| (first, address)))
+        .collect();
+
+    let mut scanned = 0;
+    for (i, (first, address)) in blocks.iter().enumerate() {
+        // keys of a block are at most the first key of the next block
+        let last = match blocks.get(i + 1) {
+            Some((next, _)) => Included((*next).clone()),
+            None => Unbounded,
+        };
+        let keys = (Included((*first).clone()), last);
+        if !ranges.iter().any(|range| ranges_overlap(range, &keys)) {
+            continue;
+        }
+        scanned += 1;
+
+        let block = address.read_record(file)?;
+        let mut rdr = ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(&block[..]);
+        let mut record = ByteRecord::new();
+        while rdr.read_byte_record(&mut record)? {
+            let start = record.position().map_or(0, |position| position.byte()) as usize;
+            let end = rdr.position().byte() as usize;
+
+            let value = key.eval(&record).and_then(|value| parse(&value));
+            if let Some(value) = value {
+                if ranges.iter().any(|range| range.contains(&value)) {
+                    printer.print_raw(&block[start..end])?;
+                }
+            }
+        }
+    }
+    debug!("scanned {} of {} blocks", scanned, blocks.len());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::{Filter, Operator};
+    use crate::index::IndexOptions;
+    use crate::test_files::CsvFile;
+
+    fn map(records: &[(u64, u64)]) -> BTreeMap<u64, Vec<Address>> {
+        let mut map = BTreeMap::new();
+        for (key, offset) in records {
+            let address = Address {
+                offset: *offset,
+                length: 10,
+            };
+            map.entry(*key).or_insert_with(Vec::new).push(address);
+        }
+        map
+    }
+
+    #[test]
+    fn test_is_sorted() {
+        assert!(is_sorted(&map(&[(1, 0), (2, 10), (2, 20), (5, 30)])));
+        assert!(!is_sorted(&map(&[(1, 0), (2, 20), (5, 10)])));
+        assert!(!is_sorted(&map(&[(1, 0), (2, 10), (5, 20), (2, 30)])));
+        assert!(is_sorted(&map(&[])));
+    }
+
+    #[test]
+    fn test_blocks() {
+        let records = [(1, 0), (2, 10), (2, 20), (2, 30), (3, 40), (4, 60)];
+        let blocks = blocks(map(&records), 2).unwrap();
+        let offsets: Vec<(u64, u64, u64)> = blocks
+            .iter()
+            .flat_map(|(key, addresses)| {
+                addresses
+                    .iter()
+                    .map(move |address| (*key, address.offset, address.length))
+            })
+            .collect();
+
+        // the record at offset 50, e.g. a null, is scanned with the last block
+        assert_eq!(offsets, vec![(1, 0, 20), (2, 20, 20), (3, 40, 30)]);
+
+        assert!(super::blocks(map(&[(1, 10), (2, 0)]), 2).is_err());
+    }
+
+    #[test]
+    fn test_print_matches() {
+        let input = b"n,name\n1,a\n2,b\n2,c\n3,d\n5,e\n5,f\n5,g\n8,h\n9,i\n";
+        let mut csv = CsvFile::new("sparse", input);
+        let n = Expression::Column(0);
+        let options = IndexOptions {
+            sorted: Some(2),
+            ..Default::default()
+        };
+        csv.index(&n, "int", &options).unwrap();
+
+        let filter = |op, value, value2| {
+            let filter = Filter::from(Operator::from(op).unwrap(), value, value2, &n);
+            csv.filter(&filter, Some(vec![1])).unwrap()
+        };
+        assert_eq!(filter("eq", "2", ""), "b\nc\n");
+        assert_eq!(filter("eq", "5", ""), "e\nf\ng\n");
+        assert_eq!(filter("eq", "4", ""), "");
+        assert_eq!(filter("in", "3", "8"), "d\ne\nf\ng\nh\n");
+        assert_eq!(filter("gt", "5", ""), "h\ni\n");
+        assert_eq!(filter("lt", "2", ""), "a\n");
+        assert_eq!(filter("ge", "10", ""), "");
+    }
+}
@@ -0,0 +1,72 @@
+//! Csv and index files on disk, for the tests of code that reads them
+
+use crate::expression::Expression;
+use crate::filter::Filter;
+use crate::index::{self, IndexOptions};
+
+use std::error::Error;
+use std::fs::{self, File};
+
+/// A csv file in the temp dir, removed together with its indexes when dropped
+pub struct CsvFile {
+    path: String,
+    indexes: Vec<String>,
+}
+
+impl CsvFile {
+    /// Write a csv file, under a name that keeps the tests running in parallel apart
+    pub fn new(name: &str, contents: &[u8]) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("text_index_{}_{}.csv", std::process::id(), name));
+        fs::write(&path, contents).unwrap();
+
+        CsvFile {
+            path: path.to_string_lossy().into_owned(),
+            indexes: vec![],
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn open(&self) -> File {
+        File::open(&self.path).unwrap()
+    }
+
+    /// Build and write the index of a key, like the index subcommand
+    pub fn index(
+        &mut self,
+        key: &Expression,
+        csv_type: &str,
+        options: &IndexOptions,
+    ) -> Result<(), Box<dyn Error>> {
+        let (index, length) = index::index(&self.path, key, csv_type, 1, options)?;
+
+        let index_path = key.index_path(&self.path);
+        self.indexes.push(index_path.clone());
+        index.serialize(File::create(index_path)?, length)?;
+
+        Ok(())
+    }
+
+    /// Output of a filter, like the filter subcommand
+    pub fn filter(
+        &self,
+        filter: &Filter,
+        select: Option<Vec<usize>>,
+    ) -> Result<String, Box<dyn Error>> {
+        let mut output = vec![];
+        filter.execute(&mut self.open(), &self.path, &mut output, select)?;
+
+        Ok(String::from_utf8(output)?)
+    }
+}
+
+impl Drop for CsvFile {
+    fn drop(&mut self) {
+        for path in self.indexes.iter().chain(Some(&self.path)) {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
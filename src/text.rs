@@ -0,0 +1,309 @@
+use crate::address::Address;
+use crate::expression::Expression;
+use crate::normalize::Normalization;
+use crate::printer::Printer;
+use crate::toc::Toc;
+use crate::trigram;
+
+use serde::{Deserialize, Serialize};
+
+use log::debug;
+use std::error::Error;
+use std::fs::File;
+use std::io::Write;
+
+const STOPWORDS: &[&str] = &[
+    "a", "an", "and", "are", "as", "at", "be", "but", "by", "for", "from", "has", "have", "he",
+    "her", "his", "i", "in", "is", "it", "its", "of", "on", "or", "she", "that", "the", "their",
+    "they", "this", "to", "was", "were", "will", "with", "you",
+];
+
+/// How the values of a text index are split into tokens, applied to queries alike
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Tokenizer {
+    normalization: Normalization,
+    stem: bool,
+    stopwords: bool,
+}
+
+impl Tokenizer {
+    pub fn new(normalization: Normalization, stem: bool, stopwords: bool) -> Self {
+        Tokenizer {
+            normalization,
+            stem,
+            stopwords,
+        }
+    }
+
+    /// Lowercased words of the value in order, split on whitespace and punctuation
+    pub fn tokens(&self, value: &[u8]) -> Vec<Vec<u8>> {
+        let value = self.normalization.fragment(value);
+        String::from_utf8_lossy(&value)
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .map(|word| word.to_lowercase())
+            .filter(|word| !(self.stopwords && STOPWORDS.contains(&word.as_ref())))
+            .map(|word| if self.stem { stem(&word) } else { word })
+            .map(String::into_bytes)
+            .collect()
+    }
+
+    /// Distinct tokens of the value, the keys it is stored under
+    pub fn keys(&self, value: &[u8]) -> Vec<Vec<u8>> {
+        let mut tokens = self.tokens(value);
+        tokens.sort_unstable();
+        tokens.dedup();
+        tokens
+    }
+}
+
+/// Strip common English inflections, so e.g. connected, connecting and connects all become connect
+fn stem(word: &str) -> String {
+    if word.len() <= 3 || !word.is_ascii() {
+        return word.to_owned();
+    }
+
+    let stem = if word.ends_with("sses") {
+        &word[..word.len() - 2]
+    } else if word.ends_with("ies") && word.len() > 4 {
+        return format!("{}y", &word[..word.len() - 3]);
+    } else if word.ends_with('s') && !word.ends_with("ss") && !word.ends_with("us") {
+        &word[..word.len() - 1]
+    } else if word.ends_with("ing") && word.len() > 5 {
+        undouble(&word[..word.len() - 3])
+    } else if word.ends_with("ed") && word.len() > 4 {
+        undouble(&word[..word.len() - 2])
+    } else {
+        word
+    };
+
+    stem.to_owned()
+}
+
+/// Remove a doubled final consonant left over by a suffix, e.g. runn(ing) becomes run
+fn undouble(stem: &str) -> &str {
+    let bytes = stem.as_bytes();
+    match bytes {
+        [.., a, b] if a == b && !b"aeioulsz".contains(b) => &stem[..stem.len() - 1],
+        _ => stem,
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Clause {
+    Term(Vec<u8>),
+    /// Tokens that should appear next to each other, in order
+    Phrase(Vec<Vec<u8>>),
+}
+
+impl Clause {
+    fn tokens(&self) -> &[Vec<u8>] {
+        match self {
+            Clause::Term(token) => std::slice::from_ref(token),
+            Clause::Phrase(tokens) => tokens,
+        }
+    }
+}
+
+/// Alternatives separated by OR, each a list of clauses that should all match
+#[derive(Debug, PartialEq)]
+struct Query(Vec<Vec<Clause>>);
+
+impl Query {
+    /// Parse e.g. `error timeout OR "connection refused"`
+    fn parse(tokenizer: &Tokenizer, query: &str) -> Self {
+        let mut alternatives = vec![];
+        let mut clauses = vec![];
+
+        for (i, part) in query.split('"').enumerate() {
+            // every odd part is enclosed by quotes
+            let words: Vec<&str> = if i % 2 == 1 {
+                vec![part]
+            } else {
+                part.split_whitespace().collect()
+            };
+
+            for word in words {
+                if i % 2 == 0 && word == "OR" {
+                    alternatives.push(std::mem::take(&mut clauses));
+                    continue;
+                }
+
+                let mut tokens = tokenizer.tokens(word.as_bytes());
+                match tokens.len() {
+                    0 => (),
+                    1 => clauses.push(Clause::Term(tokens.remove(0))),
+                    _ => clauses.push(Clause::Phrase(tokens)),
+                }
+            }
+        }
+        alternatives.push(clauses);
+
+        alternatives.retain(|clauses| !clauses.is_empty());
+        Query(alternatives)
+    }
+
+    /// Distinct tokens of all alternatives, sorted
+    fn tokens(&self) -> Vec<Vec<u8>> {
+        let mut tokens: Vec<Vec<u8>> = self
+            .0
+            .iter()
+            .flatten()
+            .flat_map(|clause| clause.tokens().iter().cloned())
+            .collect();
+        tokens.sort_unstable();
+        tokens.dedup();
+        tokens
+    }
+}
+
+/// Print the records matching the query, checking phrases against the csv file
+pub fn print_matches<W: Write>(
+    toc: Toc<Vec<u8>>,
+    tokenizer: &Tokenizer,
+    fh: &mut File,
+    file: &File,
+    key: &Expression,
+    query: &str,
+    printer: &mut Printer<W>,
+) -> Result<(), Box<dyn Error>> {
+    let query = Query::parse(tokenizer, query);
+    if query.0.is_empty() {
+        Err("The query does not contain any words to search for")?
+    }
+    debug!("query {:?}", query);
+
+    let tokens = query.tokens();
+    let postings = trigram::postings(toc, fh, &tokens)?;
+
+    let mut matches: Vec<Address> = vec![];
+    for clauses in &query.0 {
+        let lists = clauses
+            .iter()
+            .flat_map(|clause| clause.tokens())
+            .map(|token| postings[tokens.binary_search(token).unwrap()].clone())
+            .collect();
+        let candidates = trigram::intersect(lists);
+
+        let phrases: Vec<&[Vec<u8>]> = clauses
+            .iter()
+            .filter_map(|clause| match clause {
+                Clause::Phrase(tokens) => Some(&tokens[..]),
+                Clause::Term(_) => None,
+            })
+            .collect();
+        if phrases.is_empty() {
+            matches.extend(candidates);
+            continue;
+        }
+
+        for address in candidates {
+            let record = address.read_record(file)?;
+            let value = key.eval_raw(&record).unwrap_or_default();
+            let value = tokenizer.tokens(&value);
+            if phrases.iter().all(|phrase| contains_phrase(&value, phrase)) {
+                matches.push(address);
+            }
+        }
+    }
+
+    matches.sort_unstable_by_key(|address| address.offset);
+    matches.dedup_by_key(|address| address.offset);
+    debug!("{} matching records", matches.len());
+
+    for address in matches {
+        printer.print(&address, None)?;
+    }
+
+    Ok(())
+}
+
+fn contains_phrase(tokens: &[Vec<u8>], phrase: &[Vec<u8>]) -> bool {
+    tokens.windows(phrase.len()).any(|window| window == phrase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokenizer(stem: bool, stopwords: bool) -> Tokenizer {
+        Tokenizer::new(Normalization::default(), stem, stopwords)
+    }
+
+    fn words(tokens: &[Vec<u8>]) -> Vec<&str> {
+        tokens
+            .iter()
+            .map(|token| std::str::from_utf8(token).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_tokens() {
+        let tokens = tokenizer(false, false).tokens(b"The Quick-brown fox, (the) fox!");
+        assert_eq!(
+            words(&tokens),
+            vec!["the", "quick", "brown", "fox", "the", "fox"]
+        );
+
+        let keys = tokenizer(false, true).keys(b"The Quick-brown fox, (the) fox!");
+        assert_eq!(words(&keys), vec!["brown", "fox", "quick"]);
+    }
+
+    #[test]
+    fn test_stem() {
+        assert_eq!(stem("connected"), "connect");
+        assert_eq!(stem("connecting"), "connect");
+        assert_eq!(stem("connects"), "connect");
+        assert_eq!(stem("running"), "run");
+        assert_eq!(stem("falling"), "fall");
+        assert_eq!(stem("queries"), "query");
+        assert_eq!(stem("classes"), "class");
+        assert_eq!(stem("status"), "status");
+        assert_eq!(stem("bus"), "bus");
+        assert_eq!(stem("thing"), "thing");
+    }
+
+    #[test]
+    fn test_query() {
+        let tokenizer = tokenizer(false, true);
+        let query = Query::parse(
+            &tokenizer,
+            r#"error the timeout OR "Connection refused" OR of"#,
+        );
+        assert_eq!(
+            query,
+            Query(vec![
+                vec![
+                    Clause::Term(b"error".to_vec()),
+                    Clause::Term(b"timeout".to_vec())
+                ],
+                vec![Clause::Phrase(vec![
+                    b"connection".to_vec(),
+                    b"refused".to_vec()
+                ])],
+            ])
+        );
+        assert_eq!(
+            words(&query.tokens()),
+            vec!["connection", "error", "refused", "timeout"]
+        );
+
+        assert_eq!(
+            Query::parse(&tokenizer, "e-mail"),
+            Query(vec![vec![Clause::Phrase(vec![
+                b"e".to_vec(),
+                b"mail".to_vec()
+            ])]])
+        );
+        assert_eq!(Query::parse(&tokenizer, "the OR a"), Query(vec![]));
+    }
+
+    #[test]
+    fn test_contains_phrase() {
+        let tokens = tokenizer(false, false).tokens(b"connection was refused by host");
+        let phrase = |text: &[u8]| tokenizer(false, false).tokens(text);
+        assert!(contains_phrase(&tokens, &phrase(b"refused by")));
+        assert!(!contains_phrase(&tokens, &phrase(b"by refused")));
+        assert!(!contains_phrase(&tokens, &phrase(b"host name")));
+    }
+}
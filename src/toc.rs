@@ -17,37 +17,99 @@ use std::ops::Bound::{Excluded, Included, Unbounded};
 
 use flate2::read::GzDecoder;
 use std::collections::BTreeMap;
+use std::collections::HashMap;
 
 use log::debug;
 use std::fmt::Debug;
 
 use crate::address::Address;
 use crate::bits;
-use crate::csv_index::CsvIndex;
+use crate::csv_index::{each_variant, CsvIndex};
+use crate::datetime::TimeType;
+use crate::decimal::DecimalType;
+use crate::normalize::Normalization;
+use crate::number::NumberFormat;
 use crate::range::ranges_overlap;
 use crate::range::Range;
+use crate::text::Tokenizer;
 use crate::unsafe_float::UnsafeFloat;
 
+/// Start of every index file, ending in the version of the layout of the toc and chapters
+///
+/// Change the version with every change to what is serialized, files of another version have to
+/// be rebuilt.
+const FORMAT: &[u8; 8] = b"txtidx02";
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Toc<R> {
     // sorted list of byte positions
     addr: Vec<(R, Address)>,
+
+    // position of the records without a (valid) value
+    nulls: Address,
+
+    // whether a record can be stored under several keys, e.g. indexed with --split-by
+    multi_valued: bool,
+
+    // columns of which the chapters store the values of every record, see --include
+    included: Vec<usize>,
+
+    // whether the chapters only store the first key of blocks of records, see --sorted
+    sparse: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum TypedToc {
-    STR(Toc<Vec<u8>>),
-    I64(Toc<i64>),
-    F64(Toc<UnsafeFloat>),
+    STR(Toc<Vec<u8>>, Normalization),
+    I64(Toc<i64>, NumberFormat),
+    F64(Toc<UnsafeFloat>, NumberFormat),
+    TIME(Toc<i64>, TimeType),
+    DECIMAL(Toc<i64>, DecimalType),
+    U64(Toc<u64>),
+    I128(Toc<i128>),
+    U128(Toc<u128>),
+    HEX(Toc<u128>),
+    IP(Toc<u128>),
+    UUID(Toc<[u8; 16]>),
+    TRIGRAM(Toc<Vec<u8>>, Normalization),
+    TEXT(Toc<Vec<u8>>, Tokenizer),
+    HASH(Toc<u64>, Normalization),
 }
 
 impl<R: Ord + Serialize + DeserializeOwned + Clone + Debug> Toc<R> {
-    pub fn new(num_chapters: usize) -> Self {
+    pub fn new(
+        num_chapters: usize,
+        multi_valued: bool,
+        included: Vec<usize>,
+        sparse: bool,
+    ) -> Self {
         Self {
             addr: Vec::with_capacity(num_chapters),
+            nulls: Address {
+                offset: 0,
+                length: 0,
+            },
+            multi_valued,
+            included,
+            sparse,
         }
     }
 
+    /// Whether a record can match through several keys, and should be printed only once
+    pub fn is_multi_valued(&self) -> bool {
+        self.multi_valued
+    }
+
+    /// Columns of which the values are stored with the records
+    pub fn included(&self) -> &[usize] {
+        &self.included
+    }
+
+    /// Whether the addresses are blocks of records of a sorted file, to be scanned for matches
+    pub fn is_sparse(&self) -> bool {
+        self.sparse
+    }
+
     pub fn push(&mut self, value: (R, Address)) {
         self.addr.push(value);
     }
@@ -79,13 +141,18 @@ impl<R: Ord + Serialize + DeserializeOwned + Clone + Debug> Toc<R> {
         bounds
     }
 
-    pub fn find(self, bounds: &Range<R>) -> Vec<Address> {
+    /// Addresses of the chapters overlapping any of the ranges, each chapter only once
+    pub fn find(self, ranges: &[Range<R>]) -> Vec<Address> {
         let toc_bounds = self.bounds();
         debug!("toc bounds {:?}", toc_bounds);
 
         toc_bounds
             .into_iter()
-            .filter(|(_, toc_bound)| ranges_overlap(bounds, toc_bound))
+            .filter(|(_, toc_bound)| {
+                ranges
+                    .iter()
+                    .any(|bounds| ranges_overlap(bounds, toc_bound))
+            })
             .map(|(address, _)| address)
             .collect()
     }
@@ -95,21 +162,46 @@ impl<R: Ord + Serialize + DeserializeOwned + Clone + Debug> Toc<R> {
         fh: &mut File,
         bounds: &Range<R>,
     ) -> Result<Vec<CsvIndex<R>>, Box<Error>> {
-        let addresses = self.find(bounds);
+        self.get_index_ranges(fh, std::slice::from_ref(bounds))
+    }
+
+    pub fn get_index_ranges(
+        self,
+        fh: &mut File,
+        ranges: &[Range<R>],
+    ) -> Result<Vec<CsvIndex<R>>, Box<Error>> {
+        let addresses = self.find(ranges);
         debug!("need to fetch maps {:?}", addresses);
 
-        let maps = addresses
-            .into_iter()
-            .map(|address| {
-                fh.seek(SeekFrom::Start(address.offset)).unwrap();
+        addresses
+            .iter()
+            .map(|address| Self::read_chapter(fh, address))
+            .collect()
+    }
 
-                let gzh = fh.take(address.length);
-                let gz = GzDecoder::new(gzh);
-                bincode::deserialize_from(gz).unwrap()
-            })
-            .collect();
+    /// Read a single chapter, see `find` for the addresses of the chapters
+    pub fn read_chapter(fh: &mut File, address: &Address) -> Result<CsvIndex<R>, Box<dyn Error>> {
+        fh.seek(SeekFrom::Start(address.offset))?;
+
+        let gzh = fh.take(address.length);
+        let gz = GzDecoder::new(gzh);
+        Ok(bincode::deserialize_from(gz)?)
+    }
+
+    /// The records without a (valid) value, with the values of their included columns
+    pub fn get_nulls(
+        self,
+        fh: &mut File,
+    ) -> Result<(Vec<Address>, HashMap<u64, Vec<Vec<u8>>>), Box<dyn Error>> {
+        if self.nulls.length == 0 {
+            return Ok((vec![], HashMap::new()));
+        }
 
-        Ok(maps)
+        fh.seek(SeekFrom::Start(self.nulls.offset))?;
+        let gzh = fh.take(self.nulls.length);
+        let gz = GzDecoder::new(gzh);
+
+        Ok(bincode::deserialize_from(gz)?)
     }
 
     pub fn build_empty<V>(&mut self, chunked_map: &[(R, BTreeMap<R, V>)]) {
@@ -128,13 +220,17 @@ impl<R: Ord + Serialize + DeserializeOwned + Clone + Debug> Toc<R> {
         &mut self,
         mut fh: &mut File,
         chunked_map: Vec<(R, BTreeMap<R, Vec<Address>>)>,
+        included: &HashMap<u64, Vec<Vec<u8>>>,
         offset: u64,
-    ) -> Result<(), Box<dyn Error>> {
+    ) -> Result<u64, Box<dyn Error>> {
         let mut prev_pos = offset;
         let write_ops: Result<Vec<()>, Box<dyn Error>> = chunked_map
             .into_iter()
             .map(|(key, sub_map)| {
-                let index = CsvIndex::from(sub_map);
+                // every chapter holds the included values of its own records
+                let chapter_included = included_of(sub_map.values().flatten(), included);
+                let mut index = CsvIndex::from(sub_map);
+                index.set_included(chapter_included);
 
                 let gz = GzEncoder::new(&mut fh, Compression::fast());
                 bincode::serialize_into(gz, &index)?;
@@ -151,31 +247,99 @@ impl<R: Ord + Serialize + DeserializeOwned + Clone + Debug> Toc<R> {
             })
             .collect();
 
-        write_ops.map(|_| ())
+        write_ops.map(|_| prev_pos)
+    }
+
+    pub fn write_nulls(
+        &mut self,
+        mut fh: &mut File,
+        nulls: Vec<Address>,
+        included: &HashMap<u64, Vec<Vec<u8>>>,
+        offset: u64,
+    ) -> Result<(), Box<dyn Error>> {
+        if nulls.is_empty() {
+            return Ok(());
+        }
+
+        let nulls_included = included_of(nulls.iter(), included);
+        let gz = GzEncoder::new(&mut fh, Compression::fast());
+        bincode::serialize_into(gz, &(nulls, nulls_included))?;
+
+        let pos = fh.seek(SeekFrom::Current(0))?;
+        self.nulls = Address {
+            offset,
+            length: pos - offset,
+        };
+
+        Ok(())
     }
 }
 
 impl TypedToc {
     pub fn open(fh: &mut File) -> Result<TypedToc, Box<Error>> {
         let mut reader = BufReader::new(fh);
+        let mut format = [0u8; 8];
+        reader.read_exact(&mut format)?;
+        check_format(&format)?;
+
         let mut size_buffer = [0u8; 8];
         reader.read_exact(&mut size_buffer)?;
         let toc_len = bits::u8s_to_u64(size_buffer);
 
-        let toc_data = (&mut reader).take(toc_len - 8);
+        let toc_data = (&mut reader).take(toc_len - 16);
         let toc_typed: TypedToc = bincode::deserialize_from(toc_data)?;
         debug!("toc {:?}", toc_typed);
 
         Ok(toc_typed)
     }
 
+    pub fn is_sparse(&self) -> bool {
+        each_variant!(TypedToc, self, toc => toc.is_sparse())
+    }
+
     pub fn write_head(&self, mut fh: &mut File, length: u64) -> Result<(), Box<Error>> {
         if length != 0 {
             fh.seek(SeekFrom::Start(0))?;
         }
+        fh.write_all(FORMAT)?;
         fh.write_all(&bits::u64_to_u8s(length))?;
         bincode::serialize_into(&mut fh, &self)?;
 
         Ok(())
     }
 }
+
+/// The included values of some of the records
+fn included_of<'a>(
+    addresses: impl Iterator<Item = &'a Address>,
+    included: &HashMap<u64, Vec<Vec<u8>>>,
+) -> HashMap<u64, Vec<Vec<u8>>> {
+    addresses
+        .filter_map(|address| {
+            let values = included.get(&address.offset)?;
+            Some((address.offset, values.clone()))
+        })
+        .collect()
+}
+
+fn check_format(format: &[u8; 8]) -> Result<(), &'static str> {
+    if format != FORMAT {
+        return Err("The index was built by another version of text_index, rebuild the index");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_format() {
+        assert!(check_format(FORMAT).is_ok());
+        assert!(check_format(b"txtidx01").is_err());
+
+        // files without a format start with the length of the toc
+        assert!(check_format(&bits::u64_to_u8s(1234)).is_err());
+    }
+}
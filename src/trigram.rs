@@ -0,0 +1,317 @@
+use crate::address::Address;
+use crate::expression::Expression;
+use crate::normalize::Normalization;
+use crate::printer::Printer;
+use crate::range::Range;
+use crate::toc::Toc;
+
+use regex::bytes::Regex;
+use regex_syntax::hir::{Hir, HirKind, Literal, RepetitionKind, RepetitionRange};
+
+use log::debug;
+use std::error::Error;
+use std::fs::File;
+use std::io::Write;
+
+use std::ops::Bound::{Included, Unbounded};
+
+const N: usize = 3;
+
+/// What a record of a trigram index should contain
+pub enum Pattern {
+    /// A substring, already normalized like the index
+    Contains(Vec<u8>),
+    /// A regular expression, matched against the normalized value
+    Regex(Regex),
+}
+
+impl Pattern {
+    fn is_match(&self, value: &[u8]) -> bool {
+        match self {
+            Pattern::Contains(needle) => contains(value, needle),
+            Pattern::Regex(regex) => regex.is_match(value),
+        }
+    }
+
+    /// Substrings every matching (normalized) value contains
+    fn required(&self, normalization: &Normalization) -> Vec<Vec<u8>> {
+        match self {
+            Pattern::Contains(needle) => vec![needle.clone()],
+            Pattern::Regex(regex) => match regex_syntax::Parser::new().parse(regex.as_str()) {
+                Ok(hir) => {
+                    let mut literals = vec![];
+                    required_literals(&hir, &mut literals);
+                    literals
+                        .iter()
+                        .map(|literal| normalization.fragment(literal).into_owned())
+                        .collect()
+                }
+                Err(_) => vec![],
+            },
+        }
+    }
+}
+
+/// Keys of a value in a trigram index: its distinct trigrams, or the value itself when shorter
+pub fn trigrams(value: &[u8]) -> Vec<Vec<u8>> {
+    if value.len() < N {
+        return vec![value.to_vec()];
+    }
+
+    let mut grams: Vec<Vec<u8>> = value.windows(N).map(|gram| gram.to_vec()).collect();
+    grams.sort_unstable();
+    grams.dedup();
+    grams
+}
+
+/// Print the records matching the pattern, checking every candidate against the csv file
+pub fn print_matches<W: Write>(
+    toc: Toc<Vec<u8>>,
+    normalization: &Normalization,
+    fh: &mut File,
+    file: &File,
+    key: &Expression,
+    pattern: &Pattern,
+    printer: &mut Printer<W>,
+) -> Result<(), Box<dyn Error>> {
+    let mut grams: Vec<Vec<u8>> = pattern
+        .required(normalization)
+        .iter()
+        .filter(|literal| literal.len() >= N)
+        .flat_map(|literal| trigrams(literal))
+        .collect();
+    grams.sort_unstable();
+    grams.dedup();
+
+    let candidates = match pattern {
+        Pattern::Contains(needle) if needle.len() < N => scan(toc, fh, needle)?,
+        _ if grams.is_empty() => scan(toc, fh, b"")?,
+        _ => lookup(toc, fh, &grams)?,
+    };
+    debug!("{} candidate records", candidates.len());
+
+    for address in candidates {
+        let record = address.read_record(file)?;
+        let matches = key
+            .eval_raw(&record)
+            .is_some_and(|value| pattern.is_match(&normalization.key(&value)));
+        if matches {
+            printer.print_raw(&record)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Records holding all the trigrams
+fn lookup(
+    toc: Toc<Vec<u8>>,
+    fh: &mut File,
+    grams: &[Vec<u8>],
+) -> Result<Vec<Address>, Box<dyn Error>> {
+    Ok(intersect(postings(toc, fh, grams)?))
+}
+
+/// The addresses stored under every key, in the order of the keys
+pub fn postings(
+    toc: Toc<Vec<u8>>,
+    fh: &mut File,
+    keys: &[Vec<u8>],
+) -> Result<Vec<Vec<Address>>, Box<dyn Error>> {
+    let ranges: Vec<Range<Vec<u8>>> = keys
+        .iter()
+        .map(|key| (Included(key.clone()), Included(key.clone())))
+        .collect();
+    let indexes = toc.get_index_ranges(fh, &ranges)?;
+
+    let postings = keys
+        .iter()
+        .map(|key| {
+            indexes
+                .iter()
+                .filter_map(|index| index.get(key))
+                .flat_map(|addresses| addresses.iter().cloned())
+                .collect()
+        })
+        .collect();
+
+    Ok(postings)
+}
+
+/// Records holding any key that contains the fragment, by reading the whole index
+fn scan(toc: Toc<Vec<u8>>, fh: &mut File, fragment: &[u8]) -> Result<Vec<Address>, Box<dyn Error>> {
+    let indexes = toc.get_index(fh, &(Unbounded, Unbounded))?;
+
+    let mut addresses: Vec<Address> = indexes
+        .into_iter()
+        .flat_map(|index| index.into_map().into_iter())
+        .filter(|(gram, _)| contains(gram, fragment))
+        .flat_map(|(_, addresses)| addresses.into_iter())
+        .collect();
+    addresses.sort_unstable_by_key(|address| address.offset);
+    addresses.dedup_by_key(|address| address.offset);
+
+    Ok(addresses)
+}
+
+/// Addresses present in every list, sorted by offset
+pub fn intersect(mut postings: Vec<Vec<Address>>) -> Vec<Address> {
+    postings.sort_unstable_by_key(|addresses| addresses.len());
+    let mut postings = postings.into_iter();
+
+    let mut result = match postings.next() {
+        Some(addresses) => addresses,
+        None => return vec![],
+    };
+    result.sort_unstable_by_key(|address| address.offset);
+    // a record of a multi-valued column can hold a trigram in several elements
+    result.dedup_by_key(|address| address.offset);
+
+    for mut addresses in postings {
+        addresses.sort_unstable_by_key(|address| address.offset);
+        result.retain(|address| {
+            addresses
+                .binary_search_by_key(&address.offset, |a| a.offset)
+                .is_ok()
+        });
+    }
+
+    result
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    needle.is_empty()
+        || haystack
+            .windows(needle.len())
+            .any(|window| window == needle)
+}
+
+/// Collect the literal strings any match of the expression must contain
+fn required_literals(hir: &Hir, literals: &mut Vec<Vec<u8>>) {
+    match hir.kind() {
+        HirKind::Literal(_) => literals.push(literal_bytes(hir).unwrap_or_default()),
+        HirKind::Group(group) => required_literals(&group.hir, literals),
+        HirKind::Repetition(repetition) => {
+            let at_least_once = match repetition.kind {
+                RepetitionKind::OneOrMore => true,
+                RepetitionKind::Range(RepetitionRange::Exactly(n))
+                | RepetitionKind::Range(RepetitionRange::AtLeast(n))
+                | RepetitionKind::Range(RepetitionRange::Bounded(n, _)) => n > 0,
+                RepetitionKind::ZeroOrOne | RepetitionKind::ZeroOrMore => false,
+            };
+            if at_least_once {
+                required_literals(&repetition.hir, literals);
+            }
+        }
+        HirKind::Concat(hirs) => {
+            // adjacent literals form one longer string
+            let mut run = vec![];
+            for hir in hirs {
+                match literal_bytes(hir) {
+                    Some(bytes) => run.extend(bytes),
+                    None => {
+                        literals.push(std::mem::take(&mut run));
+                        required_literals(hir, literals);
+                    }
+                }
+            }
+            literals.push(run);
+        }
+        // alternations, classes and assertions do not require a specific string
+        _ => (),
+    }
+}
+
+fn literal_bytes(hir: &Hir) -> Option<Vec<u8>> {
+    match hir.kind() {
+        HirKind::Literal(Literal::Unicode(c)) => Some(c.to_string().into_bytes()),
+        HirKind::Literal(Literal::Byte(b)) => Some(vec![*b]),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::{Filter, Operator};
+    use crate::index::IndexOptions;
+    use crate::test_files::CsvFile;
+
+    fn address(offset: u64) -> Address {
+        Address { offset, length: 1 }
+    }
+
+    fn literals(pattern: &str) -> Vec<Vec<u8>> {
+        let mut literals = vec![];
+        required_literals(
+            &regex_syntax::Parser::new().parse(pattern).unwrap(),
+            &mut literals,
+        );
+        literals.retain(|literal| !literal.is_empty());
+        literals
+    }
+
+    #[test]
+    fn test_trigrams() {
+        assert_eq!(
+            trigrams(b"abcab"),
+            vec![b"abc".to_vec(), b"bca".to_vec(), b"cab".to_vec()]
+        );
+        assert_eq!(trigrams(b"ab"), vec![b"ab".to_vec()]);
+    }
+
+    #[test]
+    fn test_intersect() {
+        let postings = vec![
+            vec![address(30), address(10), address(20)],
+            vec![address(20), address(40), address(30)],
+            vec![address(30), address(20)],
+        ];
+        assert_eq!(intersect(postings), vec![address(20), address(30)]);
+        assert_eq!(intersect(vec![]), vec![]);
+    }
+
+    #[test]
+    fn test_required_literals() {
+        assert_eq!(literals("error"), vec![b"error".to_vec()]);
+        assert_eq!(
+            literals(r"^user-\d+@example\.com$"),
+            vec![b"user-".to_vec(), b"@example.com".to_vec()]
+        );
+        assert_eq!(
+            literals("(foo)+bar"),
+            vec![b"foo".to_vec(), b"bar".to_vec()]
+        );
+        assert_eq!(literals("(foo)?bar"), vec![b"bar".to_vec()]);
+        assert!(literals("foo|bar").is_empty());
+        assert!(literals("[a-z]+").is_empty());
+    }
+
+    #[test]
+    fn test_contains() {
+        assert!(contains(b"hello world", b"o w"));
+        assert!(contains(b"abc", b""));
+        assert!(!contains(b"ab", b"abc"));
+    }
+
+    #[test]
+    fn test_fold_case() {
+        let input = b"id,message\n1,Error: disk full\n2,all fine\n3,ERRORS found\n";
+        let mut csv = CsvFile::new("trigram_fold_case", input);
+        let message = Expression::Column(1);
+        let options = IndexOptions {
+            fold_case: true,
+            ..Default::default()
+        };
+        csv.index(&message, "trigram", &options).unwrap();
+
+        let filter = |op, value| {
+            let filter = Filter::from(Operator::from(op).unwrap(), value, "", &message);
+            csv.filter(&filter, Some(vec![0])).unwrap()
+        };
+        assert_eq!(filter("regex", "Error"), "1\n3\n");
+        assert_eq!(filter("regex", "error:"), "1\n");
+        assert_eq!(filter("regex", "^errors? (found|disk)"), "3\n");
+        assert_eq!(filter("contains", "Error"), "1\n3\n");
+    }
+}
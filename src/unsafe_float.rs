@@ -1,13 +1,31 @@
 use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
 
+/// A float with a total order, so it can be used as index key.
+///
+/// The ordering follows IEEE 754 totalOrder, except that -0.0 equals 0.0 and that all NaNs are
+/// equal to each other, sorting above positive infinity.
 #[derive(Serialize, Deserialize, Debug, Clone, Copy)]
 pub struct UnsafeFloat(pub f64);
 
+impl UnsafeFloat {
+    pub const NAN: UnsafeFloat = UnsafeFloat(std::f64::NAN);
+
+    fn canonical(self) -> f64 {
+        if self.0 == 0. {
+            0.
+        } else if self.0.is_nan() {
+            std::f64::NAN
+        } else {
+            self.0
+        }
+    }
+}
+
 impl Eq for UnsafeFloat {}
 impl Ord for UnsafeFloat {
     fn cmp(&self, other: &UnsafeFloat) -> Ordering {
-        self.0.partial_cmp(&other.0).unwrap_or(Ordering::Less)
+        self.canonical().total_cmp(&other.canonical())
     }
 }
 impl PartialOrd for UnsafeFloat {
@@ -17,7 +35,7 @@ impl PartialOrd for UnsafeFloat {
 }
 impl PartialEq for UnsafeFloat {
     fn eq(&self, other: &UnsafeFloat) -> bool {
-        self.0 == other.0
+        self.cmp(other) == Ordering::Equal
     }
 }
 
@@ -25,6 +43,42 @@ impl PartialEq for UnsafeFloat {
 mod tests {
     use super::*;
 
+    use crate::chunked_map::chunk_map;
+    use crate::range::{ranges_overlap, Range};
+    use std::collections::BTreeMap;
+    use std::ops::Bound::{Excluded, Included, Unbounded};
+
+    /// xorshift64, to generate reproducible test cases without extra dependencies
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn float(&mut self) -> UnsafeFloat {
+            let special = [
+                0.,
+                -0.,
+                std::f64::NAN,
+                -std::f64::NAN,
+                std::f64::INFINITY,
+                std::f64::NEG_INFINITY,
+                std::f64::MIN_POSITIVE,
+                -std::f64::MIN_POSITIVE,
+            ];
+            let x = self.next();
+            match x % 4 {
+                0 => UnsafeFloat(special[(x >> 8) as usize % special.len()]),
+                1 => UnsafeFloat(f64::from_bits(self.next())), // anything, including NaN payloads
+                _ => UnsafeFloat((self.next() % 2000) as f64 / 8. - 125.),
+            }
+        }
+    }
+
     #[test]
     fn test_sort() {
         let mut vec = vec![
@@ -42,4 +96,69 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn test_special_values() {
+        assert_eq!(UnsafeFloat(0.), UnsafeFloat(-0.));
+        assert_eq!(UnsafeFloat::NAN, UnsafeFloat::NAN);
+        assert_eq!(UnsafeFloat::NAN, UnsafeFloat(-std::f64::NAN));
+        assert!(UnsafeFloat::NAN > UnsafeFloat(std::f64::INFINITY));
+        assert!(UnsafeFloat(-1.) < UnsafeFloat(-0.));
+        assert!(UnsafeFloat(std::f64::NEG_INFINITY) < UnsafeFloat(std::f64::MIN));
+    }
+
+    #[test]
+    fn test_total_order() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        let floats: Vec<UnsafeFloat> = (0..300).map(|_| rng.float()).collect();
+
+        for a in &floats {
+            assert_eq!(a.cmp(a), Ordering::Equal);
+            for b in &floats {
+                assert_eq!(a.cmp(b), b.cmp(a).reverse());
+                assert_eq!(a == b, a.cmp(b) == Ordering::Equal);
+                for c in floats.iter().step_by(7) {
+                    if a <= b && b <= c {
+                        assert!(a <= c);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_chunks_match_toc_bounds() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+
+        for pieces in 1..8 {
+            let mut map = BTreeMap::new();
+            for i in 0..500 {
+                map.entry(rng.float()).or_insert_with(Vec::new).push(i);
+            }
+            let keys: Vec<UnsafeFloat> = map.keys().cloned().collect();
+            let chunked_map = chunk_map(&mut map, pieces);
+
+            // the ranges a Toc derives from the first key of every chapter
+            let bounds: Vec<Range<UnsafeFloat>> = (0..chunked_map.len())
+                .map(|i| {
+                    let upper = match chunked_map.get(i + 1) {
+                        Some((next, _)) => Excluded(*next),
+                        None => Unbounded,
+                    };
+                    (Included(chunked_map[i].0), upper)
+                })
+                .collect();
+
+            // every key is found in exactly the chapter holding it
+            for key in &keys {
+                let point = (Included(*key), Included(*key));
+                let found: Vec<usize> = (0..bounds.len())
+                    .filter(|&i| ranges_overlap(&point, &bounds[i]))
+                    .filter(|&i| !chunked_map[i].1.is_empty())
+                    .collect();
+                assert_eq!(found.len(), 1, "key {:?}", key);
+                assert!(chunked_map[found[0]].1.contains_key(key));
+            }
+        }
+    }
 }
@@ -0,0 +1,82 @@
+/// Read a UUID like `123e4567-e89b-12d3-a456-426614174000`, also in uppercase, braced
+/// (`{...}`), URN (`urn:uuid:...`) or plain 32 digit form, into its 16 bytes
+pub fn parse(value: &[u8]) -> Option<[u8; 16]> {
+    let mut value = std::str::from_utf8(value).ok()?.trim();
+    let urn = value
+        .get(..9)
+        .map(|prefix| prefix.eq_ignore_ascii_case("urn:uuid:"));
+    if urn == Some(true) {
+        value = &value[9..];
+    } else if value.starts_with('{') && value.ends_with('}') {
+        value = &value[1..value.len() - 1];
+    }
+
+    let digits: Vec<u8> = match value.len() {
+        32 => value.bytes().collect(),
+        36 => {
+            let hyphens = [8, 13, 18, 23];
+            if !hyphens.iter().all(|&i| value.as_bytes()[i] == b'-') {
+                return None;
+            }
+            value.bytes().filter(|&b| b != b'-').collect()
+        }
+        _ => return None,
+    };
+    if digits.len() != 32 {
+        return None;
+    }
+
+    let mut uuid = [0; 16];
+    for (byte, pair) in uuid.iter_mut().zip(digits.chunks(2)) {
+        *byte = (hex_digit(pair[0])? << 4) | hex_digit(pair[1])?;
+    }
+
+    Some(uuid)
+}
+
+/// Write a UUID in the canonical lowercase, hyphenated form
+pub fn format(uuid: &[u8; 16]) -> String {
+    let hex: String = uuid.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+fn hex_digit(b: u8) -> Option<u8> {
+    (b as char).to_digit(16).map(|d| d as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UUID: &str = "123e4567-e89b-12d3-a456-426614174000";
+
+    #[test]
+    fn test_forms() {
+        let uuid = parse(UUID.as_bytes()).unwrap();
+        assert_eq!(uuid[0], 0x12);
+        assert_eq!(uuid[15], 0x00);
+        assert_eq!(format(&uuid), UUID);
+
+        assert_eq!(parse(UUID.to_uppercase().as_bytes()), Some(uuid));
+        assert_eq!(parse(format!("{{{}}}", UUID).as_bytes()), Some(uuid));
+        assert_eq!(parse(format!("urn:uuid:{}", UUID).as_bytes()), Some(uuid));
+        assert_eq!(parse(UUID.replace("-", "").as_bytes()), Some(uuid));
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert_eq!(parse(b""), None);
+        assert_eq!(parse(b"123e4567-e89b-12d3-a456-42661417400"), None);
+        assert_eq!(parse(b"123e4567e-89b-12d3-a456-426614174000"), None);
+        assert_eq!(parse(b"123e4567-e89b-12d3-a456-42661417400g"), None);
+        assert_eq!(parse(b"{123e4567-e89b-12d3-a456-426614174000"), None);
+        assert_eq!(parse("urn:uui\u{e9}:123e4567".as_bytes()), None);
+    }
+}
@@ -0,0 +1,283 @@
+use crate::csv_index::{format_hex, FormatKey};
+use crate::expression::Expression;
+use crate::filter::Filter;
+use crate::ip;
+use crate::range::Range;
+use crate::toc::{Toc, TypedToc};
+use crate::uuid;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::error::Error;
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::Write;
+
+use std::ops::Bound::Unbounded;
+
+pub struct Values<'a> {
+    column: &'a Expression,
+    range: Option<Filter<'a>>,
+    top: Option<usize>,
+}
+
+impl<'a> Values<'a> {
+    pub fn from(column: &'a Expression, range: Option<Filter<'a>>, top: Option<usize>) -> Self {
+        Values { column, range, top }
+    }
+
+    pub fn execute<W: Write>(&self, filename: &str, writer: W) -> Result<(), Box<dyn Error>> {
+        let mut fh = File::open(self.column.index_path(filename))?;
+        let typed_toc = TypedToc::open(&mut fh)?;
+        if typed_toc.is_sparse() {
+            Err("A sparse index does not hold all values of a column")?
+        }
+
+        match typed_toc {
+            TypedToc::STR(toc, normalization) => {
+                let ranges = match &self.range {
+                    Some(filter) => filter.string_ranges(&normalization)?,
+                    None => vec![(Unbounded, Unbounded)],
+                };
+                let format_key = |key: &Vec<u8>| normalization.format_key(key);
+                self.write_counts(toc, &mut fh, &ranges, format_key, writer)
+            }
+            TypedToc::I64(toc, number_format) => {
+                let bounds = match &self.range {
+                    Some(filter) => filter.int_bounds(&number_format)?,
+                    None => (Unbounded, Unbounded),
+                };
+                self.write_counts(toc, &mut fh, &[bounds], FormatKey::format_key, writer)
+            }
+            TypedToc::F64(toc, number_format) => {
+                let bounds = match &self.range {
+                    Some(filter) => filter.float_bounds(&number_format)?,
+                    None => (Unbounded, Unbounded),
+                };
+                self.write_counts(toc, &mut fh, &[bounds], FormatKey::format_key, writer)
+            }
+            TypedToc::TIME(toc, time_type) => {
+                let bounds = match &self.range {
+                    Some(filter) => filter.time_bounds(&time_type)?,
+                    None => (Unbounded, Unbounded),
+                };
+                let format_key = |key: &i64| time_type.format_key(*key);
+                self.write_counts(toc, &mut fh, &[bounds], format_key, writer)
+            }
+            TypedToc::DECIMAL(toc, decimal_type) => {
+                let bounds = match &self.range {
+                    Some(filter) => filter.decimal_bounds(&decimal_type)?,
+                    None => (Unbounded, Unbounded),
+                };
+                let format_key = |key: &i64| decimal_type.format_key(*key);
+                self.write_counts(toc, &mut fh, &[bounds], format_key, writer)
+            }
+            TypedToc::U64(toc) => {
+                let bounds = match &self.range {
+                    Some(filter) => filter.u64_bounds()?,
+                    None => (Unbounded, Unbounded),
+                };
+                self.write_counts(toc, &mut fh, &[bounds], FormatKey::format_key, writer)
+            }
+            TypedToc::I128(toc) => {
+                let bounds = match &self.range {
+                    Some(filter) => filter.i128_bounds()?,
+                    None => (Unbounded, Unbounded),
+                };
+                self.write_counts(toc, &mut fh, &[bounds], FormatKey::format_key, writer)
+            }
+            TypedToc::U128(toc) => {
+                let bounds = match &self.range {
+                    Some(filter) => filter.u128_bounds()?,
+                    None => (Unbounded, Unbounded),
+                };
+                self.write_counts(toc, &mut fh, &[bounds], FormatKey::format_key, writer)
+            }
+            TypedToc::HEX(toc) => {
+                let bounds = match &self.range {
+                    Some(filter) => filter.hex_bounds()?,
+                    None => (Unbounded, Unbounded),
+                };
+                self.write_counts(toc, &mut fh, &[bounds], format_hex, writer)
+            }
+            TypedToc::IP(toc) => {
+                let bounds = match &self.range {
+                    Some(filter) => filter.ip_bounds()?,
+                    None => (Unbounded, Unbounded),
+                };
+                let format_key = |key: &u128| ip::format(*key).into_bytes();
+                self.write_counts(toc, &mut fh, &[bounds], format_key, writer)
+            }
+            TypedToc::UUID(toc) => {
+                let bounds = match &self.range {
+                    Some(filter) => filter.uuid_bounds()?,
+                    None => (Unbounded, Unbounded),
+                };
+                let format_key = |key: &[u8; 16]| uuid::format(key).into_bytes();
+                self.write_counts(toc, &mut fh, &[bounds], format_key, writer)
+            }
+            TypedToc::TRIGRAM(..) => Err("A trigram index does not hold the values of a column")?,
+            TypedToc::TEXT(..) => Err("A text index does not hold the values of a column")?,
+            TypedToc::HASH(..) => Err("A hash index does not hold the values of a column")?,
+        }
+    }
+
+    fn write_counts<R, F, W>(
+        &self,
+        toc: Toc<R>,
+        fh: &mut File,
+        ranges: &[Range<R>],
+        format_key: F,
+        writer: W,
+    ) -> Result<(), Box<dyn Error>>
+    where
+        R: Ord + Serialize + DeserializeOwned + Clone + Debug,
+        F: Fn(&R) -> Vec<u8>,
+        W: Write,
+    {
+        let mut wtr = csv::Writer::from_writer(writer);
+        wtr.write_record(["value", "count"])?;
+
+        let indexes = toc.get_index_ranges(fh, ranges)?;
+        let counts = indexes.iter().flat_map(|index| {
+            ranges.iter().flat_map(move |bounds| {
+                let b_clone = (bounds.0.clone(), bounds.1.clone());
+                index.value_counts(b_clone)
+            })
+        });
+
+        match self.top {
+            // chapters are sorted, so we can stream the keys in order
+            None => {
+                for (key, count) in counts {
+                    wtr.write_record(&[format_key(key), count.to_string().into_bytes()])?;
+                }
+            }
+            Some(top) => {
+                for (key, count) in most_frequent(counts, top) {
+                    wtr.write_record(&[format_key(key), count.to_string().into_bytes()])?;
+                }
+            }
+        }
+
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+/// The N most frequent keys, most frequent first, where ties go to the smallest key
+fn most_frequent<K: Ord>(counts: impl Iterator<Item = (K, usize)>, top: usize) -> Vec<(K, usize)> {
+    // a min-heap of the most frequent keys so far
+    let mut heap = BinaryHeap::with_capacity(top + 1);
+    for (key, count) in counts {
+        heap.push(Reverse((count, Reverse(key))));
+        if heap.len() > top {
+            heap.pop();
+        }
+    }
+
+    heap.into_sorted_vec()
+        .into_iter()
+        .map(|Reverse((count, Reverse(key)))| (key, count))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::Operator;
+    use crate::index::IndexOptions;
+    use crate::test_files::CsvFile;
+
+    fn values(
+        csv: &CsvFile,
+        key: &Expression,
+        range: Option<Filter>,
+        top: Option<usize>,
+    ) -> String {
+        let mut output = vec![];
+        Values::from(key, range, top)
+            .execute(csv.path(), &mut output)
+            .unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_most_frequent() {
+        let counts = vec![("a", 1), ("b", 3), ("c", 2), ("d", 3), ("e", 2)];
+        assert_eq!(
+            most_frequent(counts.clone().into_iter(), 3),
+            vec![("b", 3), ("d", 3), ("c", 2)]
+        );
+        assert_eq!(most_frequent(counts.clone().into_iter(), 1), vec![("b", 3)]);
+        assert_eq!(most_frequent(counts.clone().into_iter(), 10).len(), 5);
+        assert!(most_frequent(counts.into_iter(), 0).is_empty());
+    }
+
+    #[test]
+    fn test_range() {
+        let mut csv = CsvFile::new("values_range", b"n\n5\n1\n3\n3\n8\n5\n5\nx\n");
+        let n = Expression::Column(0);
+        csv.index(&n, "int", &IndexOptions::default()).unwrap();
+
+        // nulls are not counted
+        assert_eq!(
+            values(&csv, &n, None, None),
+            "value,count\n1,1\n3,2\n5,3\n8,1\n"
+        );
+
+        let range = Filter::from(Operator::IN, "2", "5", &n);
+        assert_eq!(
+            values(&csv, &n, Some(range), None),
+            "value,count\n3,2\n5,3\n"
+        );
+
+        let range = Filter::from(Operator::GE, "3", "", &n);
+        assert_eq!(values(&csv, &n, Some(range), Some(1)), "value,count\n5,3\n");
+    }
+
+    #[test]
+    fn test_format_key() {
+        let key = Expression::Column(0);
+        let count = |csv_type, input: &[u8], options: &IndexOptions| {
+            let mut csv = CsvFile::new(&format!("values_{}", csv_type), input);
+            csv.index(&key, csv_type, options).unwrap();
+            values(&csv, &key, None, None)
+        };
+        let options = IndexOptions::default();
+
+        assert_eq!(
+            count("hex", b"v\n0xFF\nff\n", &options),
+            "value,count\nff,2\n"
+        );
+        assert_eq!(
+            count("ip", b"v\n10.0.0.1\n::1\n", &options),
+            "value,count\n::1,1\n10.0.0.1,1\n"
+        );
+        assert_eq!(
+            count("date", b"v\n2024-02-29\n", &options),
+            "value,count\n2024-02-29,1\n"
+        );
+        let scale = IndexOptions {
+            scale: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            count("decimal", b"v\n1.5\n1.50\n", &scale),
+            "value,count\n1.50,2\n"
+        );
+
+        // str keys are shown as normalized
+        let fold_case = IndexOptions {
+            fold_case: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            count("str", b"v\nAnn\nann\n", &fold_case),
+            "value,count\nann,2\n"
+        );
+    }
+}